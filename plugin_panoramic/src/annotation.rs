@@ -0,0 +1,223 @@
+use crate::sphere;
+use crate::tile::PPanoData;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// 全景图上的手动标注点，yaw、pitch为弧度，yaw以全景图中心为0向右为正，pitch向上为正
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PMarker {
+    pub id: String,
+    pub label: String,
+    #[serde(rename = "type")]
+    pub marker_type: String,
+    pub yaw: f64,
+    pub pitch: f64,
+    /// 原图（补齐前）的像素坐标，位于补齐区域时为空
+    #[serde(default)]
+    pub pixel: Option<[f64; 2]>,
+    /// 真北方位角（度），全景图朝向未知时为空
+    pub bearing: Option<f64>,
+}
+
+impl PMarker {
+    /// 按panodata计算原图像素坐标，全景图放大后切片时需按放大后的panodata重新计算
+    pub fn locate(&mut self, panodata: &PPanoData) {
+        let (x, y) = sphere::angles_to_pixel(self.yaw, self.pitch, panodata);
+        let inside = (0.0..=panodata.cropped_width as f64).contains(&x)
+            && (0.0..=panodata.cropped_height as f64).contains(&y);
+        self.pixel = inside.then_some([x, y]);
+    }
+}
+
+/// 标注文件解析错误，包含文件路径及行号
+#[derive(Debug)]
+pub struct AnnotationError {
+    pub path: PathBuf,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AnnotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.path.display(), self.line, self.message)
+    }
+}
+
+impl std::error::Error for AnnotationError {}
+
+/// 查找与全景图同名的标注文件
+pub fn find_annotation(image: &Path) -> Option<PathBuf> {
+    ["txt", "TXT"]
+        .iter()
+        .map(|ext| image.with_extension(ext))
+        .find(|path| path.is_file())
+}
+
+/// 解析标注文件，每行一个标注点，字段以逗号分隔：
+/// `angle,yaw,pitch,类型,名称`（角度，单位度）或`pixel,x,y,类型,名称`（原图像素坐标），
+/// 空行及#开头的行忽略。返回解析成功的标注点及各行的错误
+pub fn parse_annotations(
+    path: &Path,
+    text: &str,
+    imagename: &str,
+    panodata: &PPanoData,
+) -> (Vec<PMarker>, Vec<AnnotationError>) {
+    let mut markers = Vec::new();
+    let mut errors = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim().trim_start_matches('\u{feff}');
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| AnnotationError {
+            path: path.to_path_buf(),
+            line: index + 1,
+            message,
+        };
+        let fields: Vec<&str> = line.splitn(5, ',').map(str::trim).collect();
+        if fields.len() < 5 {
+            errors.push(error(format!(
+                "字段数量不足，应为`angle|pixel,值1,值2,类型,名称`：{}",
+                line
+            )));
+            continue;
+        }
+        let (a, b) = match (fields[1].parse::<f64>(), fields[2].parse::<f64>()) {
+            (Ok(a), Ok(b)) if a.is_finite() && b.is_finite() => (a, b),
+            _ => {
+                errors.push(error(format!(
+                    "坐标{},{}不是有效数字",
+                    fields[1], fields[2]
+                )));
+                continue;
+            }
+        };
+        let (yaw, pitch) = match fields[0].to_ascii_lowercase().as_str() {
+            "angle" => {
+                if !(-90.0..=90.0).contains(&b) {
+                    errors.push(error(format!("俯仰角{}超出[-90,90]", b)));
+                    continue;
+                }
+                (a.to_radians(), b.to_radians())
+            }
+            "pixel" => {
+                if a < 0.0
+                    || b < 0.0
+                    || a > panodata.cropped_width as f64
+                    || b > panodata.cropped_height as f64
+                {
+                    errors.push(error(format!("像素坐标{},{}超出图片范围", a, b)));
+                    continue;
+                }
+                sphere::pixel_to_angles(a, b, panodata)
+            }
+            other => {
+                errors.push(error(format!("未知的坐标类型{}，应为angle或pixel", other)));
+                continue;
+            }
+        };
+        if fields[3].is_empty() {
+            errors.push(error("标注类型不能为空".to_string()));
+            continue;
+        }
+        let mut marker = PMarker {
+            id: format!("{}-{}", imagename, markers.len() + 1),
+            label: fields[4].to_string(),
+            marker_type: fields[3].to_string(),
+            yaw,
+            pitch,
+            pixel: None,
+            bearing: None,
+        };
+        marker.locate(panodata);
+        markers.push(marker);
+    }
+    (markers, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> (Vec<PMarker>, Vec<AnnotationError>) {
+        parse_annotations(
+            Path::new("g/pano.txt"),
+            text,
+            "pano",
+            &PPanoData::centered(800, 300),
+        )
+    }
+
+    #[test]
+    fn parses_angle_and_pixel_lines() {
+        let text = "\u{feff}# 类型,名称\n\
+                    angle, 90, -30, pole, 杆1\n\
+                    \n\
+                    PIXEL,400,150,sign,牌,含逗号\n";
+        let (markers, errors) = parse(text);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(markers.len(), 2);
+        assert_eq!(markers[0].id, "pano-1");
+        assert_eq!(
+            (markers[0].marker_type.as_str(), markers[0].label.as_str()),
+            ("pole", "杆1")
+        );
+        assert!((markers[0].yaw - 90f64.to_radians()).abs() < 1e-9);
+        assert!((markers[0].pitch + 30f64.to_radians()).abs() < 1e-9);
+        //居中补齐后原图中心即全景图中心
+        assert_eq!(markers[1].id, "pano-2");
+        assert_eq!(markers[1].label, "牌,含逗号");
+        assert!(markers[1].yaw.abs() < 1e-9 && markers[1].pitch.abs() < 1e-9);
+        assert!(markers[1].bearing.is_none());
+        let [x, y] = markers[1].pixel.unwrap();
+        assert!((x - 400.0).abs() < 1e-9 && (y - 150.0).abs() < 1e-9);
+        //原图上下各补齐50像素，俯仰角-30度对应原图内的像素
+        let [x, y] = markers[0].pixel.unwrap();
+        assert!((x - 600.0).abs() < 1e-9 && (y - 216.666_666_667).abs() < 1e-6);
+        let (markers, _) = parse("angle,0,80,pole,杆\n");
+        assert!(markers[0].pixel.is_none());
+    }
+
+    #[test]
+    fn locate_follows_scaled_panodata() {
+        let (mut markers, _) = parse("pixel,400,150,sign,牌\nangle,90,-30,pole,杆\n");
+        let scaled = PPanoData::centered(800, 300).scaled(1600);
+        for marker in &mut markers {
+            marker.locate(&scaled);
+        }
+        let [x, y] = markers[0].pixel.unwrap();
+        assert!((x - 800.0).abs() < 1e-9 && (y - 300.0).abs() < 1e-9);
+        let [x, y] = markers[1].pixel.unwrap();
+        assert!((x - 1200.0).abs() < 1e-9 && (y - 433.333_333_333).abs() < 1e-6);
+    }
+
+    #[test]
+    fn reports_invalid_lines() {
+        let text = "angle,10,5,pole\n\
+                    angle,10,95,pole,杆\n\
+                    pixel,801,10,pole,杆\n\
+                    pixel,10,-1,pole,杆\n\
+                    angle,x,5,pole,杆\n\
+                    polar,10,5,pole,杆\n\
+                    angle,10,5,,杆\n\
+                    angle,10,5,pole,杆\n";
+        let (markers, errors) = parse(text);
+        assert_eq!(markers.len(), 1);
+        //有效行的编号不受前面错误行的影响
+        assert_eq!(markers[0].id, "pano-1");
+        let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, [1, 2, 3, 4, 5, 6, 7]);
+        assert!(errors[0].message.starts_with("字段数量不足"));
+        assert!(errors[1].message.contains("俯仰角95"));
+        assert!(errors[2].message.contains("超出图片范围"));
+        assert!(errors[5].message.contains("未知的坐标类型polar"));
+        assert_eq!(
+            errors[1].to_string(),
+            format!(
+                "{}:2: 俯仰角95超出[-90,90]",
+                Path::new("g/pano.txt").display()
+            )
+        );
+    }
+}
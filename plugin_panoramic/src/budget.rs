@@ -0,0 +1,165 @@
+use crate::tile::{SmallMode, TileMode, TileOptions};
+use crate::MIN_SIZE;
+use image::ImageDecoder;
+use std::fs;
+use std::path::Path;
+use std::sync::{Condvar, Mutex};
+
+/// 估算编译单张全景图占用的内存：原始文件、解码后的图像、转为8位RGB的图像，
+/// 以及放大、重采样、金字塔下一级和立方体各面的缓冲
+pub fn estimate_bytes(path: &Path, options: &TileOptions) -> u64 {
+    let file_size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let decoded = image::io::Reader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .ok()
+        .and_then(|reader| reader.into_decoder().ok())
+        .map(|decoder| {
+            let (width, height) = decoder.dimensions();
+            decoder.total_bytes() + rgb_bytes(width, height) + working_bytes(width, height, options)
+        })
+        .unwrap_or(0);
+    file_size + decoded
+}
+
+fn rgb_bytes(width: u32, height: u32) -> u64 {
+    width as u64 * height as u64 * 3
+}
+
+//切片过程中额外的缓冲，未读取GPano时按补齐为2:1的全景估算
+fn working_bytes(width: u32, height: u32, options: &TileOptions) -> u64 {
+    let full_width = width.max(height * 2).max(1);
+    let is_max = full_width >= MIN_SIZE;
+    if !is_max && options.small == SmallMode::Single {
+        //补齐后整张输出
+        let canvas = rgb_bytes(full_width, full_width / 2);
+        return canvas + cubemap_bytes(full_width, options);
+    }
+    let (mut total, width, height, full_width) = if is_max {
+        (0, width, height, full_width)
+    } else {
+        let scale = |v: u32| (v as u64 * MIN_SIZE as u64 / full_width as u64) as u32;
+        let (width, height) = (scale(width), scale(height));
+        (rgb_bytes(width, height), width, height, MIN_SIZE)
+    };
+    if options.mode == TileMode::Resample {
+        total += rgb_bytes(width, height);
+    }
+    //金字塔逐级生成，同时只保留一级缩小的图像，最大为第1级
+    if options.levels > 1 {
+        total += rgb_bytes(width.div_ceil(2), height.div_ceil(2));
+    }
+    total + cubemap_bytes(full_width, options)
+}
+
+//六个面并行渲染
+fn cubemap_bytes(full_width: u32, options: &TileOptions) -> u64 {
+    if !options.cubemap {
+        return 0;
+    }
+    let facesize = options.facesize.unwrap_or(full_width / 4);
+    6 * rgb_bytes(facesize, facesize)
+}
+
+/// 全部分组共享的内存预算，编译前按估算预留，编译完成后释放，
+/// 单张超过预算时等其余全景图完成后单独编译
+pub struct MemoryBudget {
+    limit: u64,
+    used: Mutex<u64>,
+    released: Condvar,
+}
+
+/// 一张全景图预留的内存，释放时唤醒等待的全景图
+pub struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> MemoryBudget {
+        MemoryBudget {
+            limit,
+            used: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// 预留bytes字节，超出预算时阻塞当前线程，不应在编译线程中调用
+    pub fn acquire(&self, bytes: u64) -> Reservation<'_> {
+        let mut used = self.used.lock().unwrap();
+        while *used > 0 && used.saturating_add(bytes) > self.limit {
+            used = self.released.wait(used).unwrap();
+        }
+        *used = used.saturating_add(bytes);
+        Reservation {
+            budget: self,
+            bytes,
+        }
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let mut used = self.budget.used.lock().unwrap();
+        *used -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn reservations_wait_for_budget() {
+        let budget = MemoryBudget::new(5);
+        //单张超过预算时仍可编译
+        drop(budget.acquire(10));
+        let first = budget.acquire(3);
+        let second_done = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let _second = budget.acquire(3);
+                second_done.store(true, Ordering::SeqCst);
+            });
+            std::thread::sleep(Duration::from_millis(50));
+            assert!(!second_done.load(Ordering::SeqCst));
+            drop(first);
+        });
+        assert!(second_done.load(Ordering::SeqCst));
+        assert_eq!(*budget.used.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn estimate_includes_working_buffers() {
+        let folder = testutil::temp_folder("budget-estimate");
+        let path = folder.join("p.png");
+        image::RgbImage::new(200, 100).save(&path).unwrap();
+        let options = testutil::tile_options(TileMode::Truncate, 1);
+        let plain = estimate_bytes(&path, &options);
+        let cubemap = estimate_bytes(
+            &path,
+            &TileOptions {
+                cubemap: true,
+                ..options.clone()
+            },
+        );
+        let upscale = estimate_bytes(
+            &path,
+            &TileOptions {
+                small: SmallMode::Upscale,
+                levels: 3,
+                ..options
+            },
+        );
+        let _ = fs::remove_dir_all(&folder);
+        assert_eq!(cubemap - plain, 6 * rgb_bytes(50, 50));
+        //放大后的图像及金字塔第1级替代补齐后的整张输出
+        assert_eq!(
+            upscale + rgb_bytes(200, 100) - plain,
+            rgb_bytes(5000, 2500) + rgb_bytes(2500, 1250)
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use crate::manifest::Manifest;
+use crate::source::OutputFolders;
+use crate::tile::PPanoData;
+use crate::{annotation, metadata, source, xmp, MIN_SIZE};
+use glob::{glob_with, MatchOptions};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// 检查项
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckKind {
+    /// 无法读取或解析的图片
+    Unreadable,
+    /// 不是完整的2:1全景，编译时会补齐
+    NotEquirect,
+    /// 宽度不足MIN_SIZE，默认不切片
+    TooSmall,
+    /// 缺少GPS经纬度
+    NoGps,
+    /// 存在同名全景图，与编译时一样不区分大小写，同一分组中重名时编译失败
+    DuplicateName,
+    /// 标注文件没有对应的全景图
+    OrphanAnnotation,
+    /// 全景图没有标注文件
+    NoAnnotation,
+    /// 直接放在输入根目录中，不会被编译
+    RootFile,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PCheckIssue {
+    pub kind: CheckKind,
+    /// 相对于输入目录的路径
+    pub source: String,
+    pub message: String,
+}
+
+/// 检查报告
+#[derive(Serialize, Debug, Default)]
+pub struct CheckReport {
+    pub groups: usize,
+    pub images: usize,
+    pub issues: Vec<PCheckIssue>,
+}
+
+impl CheckReport {
+    fn push(&mut self, kind: CheckKind, source: &str, message: impl ToString) {
+        self.issues.push(PCheckIssue {
+            kind,
+            source: source.to_string(),
+            message: message.to_string(),
+        });
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+//与编译时相同，扩展名不区分大小写
+fn list_files(folder: &Path, ext: &str) -> Vec<PathBuf> {
+    let options = MatchOptions {
+        case_sensitive: false,
+        ..Default::default()
+    };
+    match glob_with(&source::extension_pattern(folder, ext), options) {
+        Ok(paths) => paths.filter_map(|x| x.ok()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// 检查输入目录，不读取完整图像，不写出任何文件；
+/// 输出目录位于输入目录中时，与编译时一样跳过其中的编译输出
+pub fn check_input(input: &Path, output: &Path) -> std::io::Result<CheckReport> {
+    let outputs = OutputFolders::new(output, &Manifest::load(output));
+    let mut report = CheckReport::default();
+    for path in source::list_sources(input)
+        .iter()
+        .chain(list_files(input, "txt").iter())
+    {
+        report.push(
+            CheckKind::RootFile,
+            &file_name(path),
+            "文件不在分组目录中，不会被编译",
+        );
+    }
+    //全景图名称及其所在位置，用于检查重名
+    let mut names: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for folder in source::group_folders(input, &outputs)? {
+        let group = file_name(&folder);
+        check_group(&folder, &group, &outputs, &mut report, &mut names)?;
+    }
+    for sources in names.values().filter(|sources| sources.len() > 1) {
+        for source in sources {
+            let others: Vec<&str> = sources
+                .iter()
+                .filter(|other| *other != source)
+                .map(|other| other.as_str())
+                .collect();
+            report.push(
+                CheckKind::DuplicateName,
+                source,
+                format!("与{}同名", others.join("、")),
+            );
+        }
+    }
+    report.issues.sort_by(|a, b| a.source.cmp(&b.source));
+    Ok(report)
+}
+
+//检查分组及其全部子分组
+fn check_group(
+    folder: &Path,
+    group: &str,
+    outputs: &OutputFolders,
+    report: &mut CheckReport,
+    names: &mut BTreeMap<String, Vec<String>>,
+) -> std::io::Result<()> {
+    report.groups += 1;
+    let images = source::list_sources(folder);
+    let mut annotations = Vec::new();
+    for image in &images {
+        report.images += 1;
+        let source = format!("{}/{}", group, file_name(image));
+        names
+            .entry(source::stem_key(image))
+            .or_default()
+            .push(source.clone());
+        match annotation::find_annotation(image) {
+            Some(path) => annotations.push(path),
+            None => report.push(CheckKind::NoAnnotation, &source, "没有同名标注文件"),
+        }
+        check_image(image, &source, report);
+    }
+    for path in list_files(folder, "txt") {
+        if !annotations.contains(&path) {
+            report.push(
+                CheckKind::OrphanAnnotation,
+                &format!("{}/{}", group, file_name(&path)),
+                "没有同名全景图",
+            );
+        }
+    }
+    for child in source::group_folders(folder, outputs)? {
+        let id = format!("{}/{}", group, file_name(&child));
+        //全景图输出目录与子分组输出目录相同
+        if images
+            .iter()
+            .any(|image| source::stem_key(image) == source::folder_key(&child))
+        {
+            report.push(
+                CheckKind::DuplicateName,
+                &id,
+                "与同级全景图同名，输出目录冲突",
+            );
+        }
+        check_group(&child, &id, outputs, report, names)?;
+    }
+    Ok(())
+}
+
+fn check_image(path: &Path, source: &str, report: &mut CheckReport) {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            report.push(CheckKind::Unreadable, source, e);
+            return;
+        }
+    };
+    let dimensions = image::io::Reader::new(Cursor::new(&data))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())
+        .and_then(|reader| reader.into_dimensions().map_err(|e| e.to_string()));
+    let (width, height) = match dimensions {
+        Ok(dimensions) => dimensions,
+        Err(e) => {
+            report.push(CheckKind::Unreadable, source, e);
+            return;
+        }
+    };
+    //与编译时一致，GPano裁切信息有效时按完整全景的尺寸检查
+    let panodata = xmp::read_xmp(&data)
+        .as_deref()
+        .and_then(xmp::read_gpano)
+        .and_then(|gpano| PPanoData::from_gpano(&gpano, width, height))
+        .unwrap_or_else(|| PPanoData::centered(width, height));
+    if !panodata.is_full() {
+        report.push(
+            CheckKind::NotEquirect,
+            source,
+            format!(
+                "{}x{}不是完整的2:1全景，编译时补齐为{}x{}",
+                width, height, panodata.full_width, panodata.full_height
+            ),
+        );
+    }
+    if panodata.full_width < MIN_SIZE {
+        report.push(
+            CheckKind::TooSmall,
+            source,
+            format!("宽度{}不足{}", panodata.full_width, MIN_SIZE),
+        );
+    }
+    match exif::Reader::new().read_from_container(&mut Cursor::new(&data)) {
+        Ok(exif) if metadata::has_gps(&exif) => {}
+        Ok(_) => report.push(CheckKind::NoGps, source, "exif中缺少GPS经纬度"),
+        Err(e) => report.push(CheckKind::NoGps, source, format!("无法读取exif：{}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use image::RgbImage;
+
+    #[test]
+    fn reports_issue_kinds() {
+        let input = testutil::temp_folder("check");
+        fs::create_dir_all(input.join("A/p1")).unwrap();
+        fs::create_dir_all(input.join("B")).unwrap();
+        fs::write(input.join("root.jpg"), b"").unwrap();
+        fs::write(input.join("A/bad.jpg"), b"not a jpeg").unwrap();
+        fs::write(input.join("A/orphan.txt"), b"").unwrap();
+        fs::write(input.join("A/p1.txt"), b"").unwrap();
+        for (path, width) in [("A/p1.png", 200), ("B/p1.png", 200), ("B/wide.png", 300)] {
+            RgbImage::new(width, 100).save(input.join(path)).unwrap();
+        }
+        //位于输入目录中的输出目录
+        fs::create_dir_all(input.join("out/A")).unwrap();
+        RgbImage::new(200, 100)
+            .save(input.join("out/A/x.png"))
+            .unwrap();
+
+        let report = check_input(&input, &input.join("out")).unwrap();
+        let _ = fs::remove_dir_all(&input);
+        //A/p1不是编译清单中记录的输出目录，作为子分组检查
+        assert_eq!((report.groups, report.images), (3, 4));
+        let json = serde_json::to_value(&report).unwrap();
+        let issues: Vec<(&str, &str)> = json["issues"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|issue| {
+                (
+                    issue["source"].as_str().unwrap(),
+                    issue["kind"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            issues,
+            [
+                ("A/bad.jpg", "no_annotation"),
+                ("A/bad.jpg", "unreadable"),
+                ("A/orphan.txt", "orphan_annotation"),
+                //子分组与全景图同名
+                ("A/p1", "duplicate_name"),
+                ("A/p1.png", "too_small"),
+                ("A/p1.png", "no_gps"),
+                ("A/p1.png", "duplicate_name"),
+                ("B/p1.png", "no_annotation"),
+                ("B/p1.png", "too_small"),
+                ("B/p1.png", "no_gps"),
+                ("B/p1.png", "duplicate_name"),
+                ("B/wide.png", "no_annotation"),
+                ("B/wide.png", "not_equirect"),
+                ("B/wide.png", "too_small"),
+                ("B/wide.png", "no_gps"),
+                ("root.jpg", "root_file"),
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicate_names_ignore_case() {
+        let input = testutil::temp_folder("check-case");
+        fs::create_dir_all(input.join("G")).unwrap();
+        //扩展名不同，不依赖文件系统是否区分大小写
+        for path in ["G/A.png", "G/a.jpg", "G/b.png"] {
+            RgbImage::new(200, 100).save(input.join(path)).unwrap();
+        }
+        let report = check_input(&input, &input).unwrap();
+        let _ = fs::remove_dir_all(&input);
+        let duplicates: Vec<&str> = report
+            .issues
+            .iter()
+            .filter(|issue| issue.kind == CheckKind::DuplicateName)
+            .map(|issue| issue.source.as_str())
+            .collect();
+        assert_eq!(duplicates, ["G/A.png", "G/a.jpg"]);
+    }
+}
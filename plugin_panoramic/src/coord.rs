@@ -0,0 +1,180 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+/// 克拉索夫斯基椭球长半轴及偏心率平方，GCJ-02加密算法使用
+const KRASOVSKY_A: f64 = 6378245.0;
+const KRASOVSKY_EE: f64 = 0.006_693_421_622_965_943;
+const X_PI: f64 = PI * 3000.0 / 180.0;
+
+/// 输出坐标系
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CoordSystem {
+    /// GPS原始坐标
+    #[default]
+    Wgs84,
+    /// 国测局坐标，高德、腾讯地图使用
+    Gcj02,
+    /// 百度坐标
+    Bd09,
+}
+
+/// 中国境外不做偏移
+pub fn out_of_china(lon: f64, lat: f64) -> bool {
+    !(72.004..=137.8347).contains(&lon) || !(0.8293..=55.8271).contains(&lat)
+}
+
+fn transform_lat(x: f64, y: f64) -> f64 {
+    let mut ret = -100.0 + 2.0 * x + 3.0 * y + 0.2 * y * y + 0.1 * x * y + 0.2 * x.abs().sqrt();
+    ret += (20.0 * (6.0 * x * PI).sin() + 20.0 * (2.0 * x * PI).sin()) * 2.0 / 3.0;
+    ret += (20.0 * (y * PI).sin() + 40.0 * (y / 3.0 * PI).sin()) * 2.0 / 3.0;
+    ret += (160.0 * (y / 12.0 * PI).sin() + 320.0 * (y * PI / 30.0).sin()) * 2.0 / 3.0;
+    ret
+}
+
+fn transform_lon(x: f64, y: f64) -> f64 {
+    let mut ret = 300.0 + x + 2.0 * y + 0.1 * x * x + 0.1 * x * y + 0.1 * x.abs().sqrt();
+    ret += (20.0 * (6.0 * x * PI).sin() + 20.0 * (2.0 * x * PI).sin()) * 2.0 / 3.0;
+    ret += (20.0 * (x * PI).sin() + 40.0 * (x / 3.0 * PI).sin()) * 2.0 / 3.0;
+    ret += (150.0 * (x / 12.0 * PI).sin() + 300.0 * (x / 30.0 * PI).sin()) * 2.0 / 3.0;
+    ret
+}
+
+pub fn wgs84_to_gcj02(lon: f64, lat: f64) -> (f64, f64) {
+    if out_of_china(lon, lat) {
+        return (lon, lat);
+    }
+    let dlat = transform_lat(lon - 105.0, lat - 35.0);
+    let dlon = transform_lon(lon - 105.0, lat - 35.0);
+    let radlat = lat.to_radians();
+    let magic = 1.0 - KRASOVSKY_EE * radlat.sin() * radlat.sin();
+    let sqrtmagic = magic.sqrt();
+    let dlat = (dlat * 180.0) / ((KRASOVSKY_A * (1.0 - KRASOVSKY_EE)) / (magic * sqrtmagic) * PI);
+    let dlon = (dlon * 180.0) / (KRASOVSKY_A / sqrtmagic * radlat.cos() * PI);
+    (lon + dlon, lat + dlat)
+}
+
+/// GCJ-02反算WGS84，迭代求解，精度优于1e-9度
+pub fn gcj02_to_wgs84(lon: f64, lat: f64) -> (f64, f64) {
+    if out_of_china(lon, lat) {
+        return (lon, lat);
+    }
+    let (mut wlon, mut wlat) = (lon, lat);
+    for _ in 0..30 {
+        let (glon, glat) = wgs84_to_gcj02(wlon, wlat);
+        let (dlon, dlat) = (glon - lon, glat - lat);
+        wlon -= dlon;
+        wlat -= dlat;
+        if dlon.abs() < 1e-10 && dlat.abs() < 1e-10 {
+            break;
+        }
+    }
+    (wlon, wlat)
+}
+
+pub fn gcj02_to_bd09(lon: f64, lat: f64) -> (f64, f64) {
+    let z = (lon * lon + lat * lat).sqrt() + 0.00002 * (lat * X_PI).sin();
+    let theta = lat.atan2(lon) + 0.000003 * (lon * X_PI).cos();
+    (z * theta.cos() + 0.0065, z * theta.sin() + 0.006)
+}
+
+/// BD-09反算GCJ-02，先用近似公式再迭代修正，精度优于1e-9度
+pub fn bd09_to_gcj02(lon: f64, lat: f64) -> (f64, f64) {
+    let x = lon - 0.0065;
+    let y = lat - 0.006;
+    let z = (x * x + y * y).sqrt() - 0.00002 * (y * X_PI).sin();
+    let theta = y.atan2(x) - 0.000003 * (x * X_PI).cos();
+    let (mut glon, mut glat) = (z * theta.cos(), z * theta.sin());
+    for _ in 0..30 {
+        let (blon, blat) = gcj02_to_bd09(glon, glat);
+        let (dlon, dlat) = (blon - lon, blat - lat);
+        glon -= dlon;
+        glat -= dlat;
+        if dlon.abs() < 1e-10 && dlat.abs() < 1e-10 {
+            break;
+        }
+    }
+    (glon, glat)
+}
+
+/// 坐标系之间的转换
+pub fn convert(lon: f64, lat: f64, from: CoordSystem, to: CoordSystem) -> (f64, f64) {
+    if from == to {
+        return (lon, lat);
+    }
+    let (lon, lat) = match from {
+        CoordSystem::Wgs84 => (lon, lat),
+        CoordSystem::Gcj02 => gcj02_to_wgs84(lon, lat),
+        CoordSystem::Bd09 => {
+            let (lon, lat) = bd09_to_gcj02(lon, lat);
+            gcj02_to_wgs84(lon, lat)
+        }
+    };
+    match to {
+        CoordSystem::Wgs84 => (lon, lat),
+        CoordSystem::Gcj02 => wgs84_to_gcj02(lon, lat),
+        CoordSystem::Bd09 => {
+            let (lon, lat) = wgs84_to_gcj02(lon, lat);
+            gcj02_to_bd09(lon, lat)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: [(f64, f64); 4] = [
+        (116.397_428, 39.909_23),
+        (121.473_701, 31.230_416),
+        (113.264_385, 23.129_112),
+        (87.617_733, 43.792_818),
+    ];
+    const ALL: [CoordSystem; 3] = [CoordSystem::Wgs84, CoordSystem::Gcj02, CoordSystem::Bd09];
+
+    #[test]
+    fn gcj02_offset_is_hundreds_of_meters() {
+        for (lon, lat) in SAMPLES {
+            let (glon, glat) = wgs84_to_gcj02(lon, lat);
+            let d = crate::geodesy::distance(lon, lat, glon, glat);
+            assert!((100.0..800.0).contains(&d), "{} {} offset {}", lon, lat, d);
+        }
+    }
+
+    #[test]
+    fn known_gcj02_value() {
+        //天安门，WGS84与GCJ-02对照
+        let (lon, lat) = wgs84_to_gcj02(116.391_248, 39.907_374);
+        assert!((lon - 116.397_486).abs() < 2e-5, "{}", lon);
+        assert!((lat - 39.908_777).abs() < 2e-5, "{}", lat);
+    }
+
+    #[test]
+    fn round_trip() {
+        for (lon, lat) in SAMPLES {
+            for from in ALL {
+                for to in ALL {
+                    let (x, y) = convert(lon, lat, from, to);
+                    let (rx, ry) = convert(x, y, to, from);
+                    assert!(
+                        (rx - lon).abs() < 1e-8 && (ry - lat).abs() < 1e-8,
+                        "{:?}->{:?} {},{} -> {},{}",
+                        from,
+                        to,
+                        lon,
+                        lat,
+                        rx,
+                        ry
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn outside_china_is_unchanged() {
+        assert_eq!(wgs84_to_gcj02(2.35, 48.85), (2.35, 48.85));
+        assert_eq!(gcj02_to_wgs84(-70.5, -33.4), (-70.5, -33.4));
+    }
+}
@@ -0,0 +1,235 @@
+use crate::sphere;
+use crate::tile::{PaddedImage, TileOptions};
+use image::{GenericImageView, Rgb, RgbImage};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// 立方体六个面，顺序与Photo Sphere Viewer cubemap适配器一致
+pub const FACES: [&str; 6] = ["left", "front", "right", "back", "top", "bottom"];
+
+/// 立方体贴图输出信息
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PCubemap {
+    /// 每个面的边长
+    pub facesize: u32,
+    /// 每个面每边的切片数，1表示不切片
+    pub nbtiles: u32,
+    /// 立方体贴图所在目录，相对于输出根目录
+    pub path: String,
+    /// 各面图片文件（不切片）或切片目录（切片），相对于path
+    pub faces: PCubeFaces,
+    /// 切片时面内切片的文件名模板
+    pub template: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PCubeFaces {
+    pub left: String,
+    pub front: String,
+    pub right: String,
+    pub back: String,
+    pub top: String,
+    pub bottom: String,
+}
+
+/// 面内像素对应的视线方向，a、b为[-1,1]内的面坐标，b向下为正。
+/// 坐标系以全景图中心为前方(+z)，右方为+x，上方为+y
+fn face_direction(face: usize, a: f64, b: f64) -> (f64, f64, f64) {
+    match face {
+        0 => (-1.0, -b, a),
+        1 => (a, -b, 1.0),
+        2 => (1.0, -b, -a),
+        3 => (-a, -b, -1.0),
+        4 => (a, 1.0, b),
+        _ => (a, -1.0, -b),
+    }
+}
+
+/// 双线性采样等距圆柱投影图像，水平方向循环，垂直方向截断
+pub fn sample_bilinear(img: &PaddedImage, u: f64, v: f64) -> Rgb<u8> {
+    let width = img.width() as i64;
+    let height = img.height() as i64;
+    let x0 = u.floor();
+    let y0 = v.floor();
+    let fx = u - x0;
+    let fy = v - y0;
+    let px = |x: i64| x.rem_euclid(width) as u32;
+    let py = |y: i64| y.clamp(0, height - 1) as u32;
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let p00 = img.get_pixel(px(x0), py(y0));
+    let p10 = img.get_pixel(px(x0 + 1), py(y0));
+    let p01 = img.get_pixel(px(x0), py(y0 + 1));
+    let p11 = img.get_pixel(px(x0 + 1), py(y0 + 1));
+    let mut result = [0u8; 3];
+    for c in 0..3 {
+        let top = p00[c] as f64 * (1.0 - fx) + p10[c] as f64 * fx;
+        let bottom = p01[c] as f64 * (1.0 - fx) + p11[c] as f64 * fx;
+        result[c] = (top * (1.0 - fy) + bottom * fy).round().clamp(0.0, 255.0) as u8;
+    }
+    Rgb(result)
+}
+
+/// 将等距圆柱投影全景图重投影为立方体的一个面
+pub fn render_face(img: &PaddedImage, face: usize, facesize: u32) -> RgbImage {
+    let (width, height) = (img.width(), img.height());
+    let mut buf = vec![0u8; (facesize * facesize * 3) as usize];
+    buf.par_chunks_mut((facesize * 3) as usize)
+        .enumerate()
+        .for_each(|(j, row)| {
+            let b = 2.0 * (j as f64 + 0.5) / facesize as f64 - 1.0;
+            for i in 0..facesize as usize {
+                let a = 2.0 * (i as f64 + 0.5) / facesize as f64 - 1.0;
+                let (x, y, z) = face_direction(face, a, b);
+                let (yaw, pitch) = sphere::vector_to_angles(x, y, z);
+                let (u, v) = sphere::angles_to_canvas(yaw, pitch, width, height);
+                let pixel = sample_bilinear(img, u - 0.5, v - 0.5);
+                row[i * 3..i * 3 + 3].copy_from_slice(&pixel.0);
+            }
+        });
+    RgbImage::from_raw(facesize, facesize, buf).unwrap()
+}
+
+/// 输出立方体贴图，nbtiles大于1时每个面再按nbtiles x nbtiles切片
+pub fn clip_cubemap(
+    img: &PaddedImage,
+    options: &TileOptions,
+    folder: &Path,
+    relpath: &str,
+) -> Result<PCubemap, Box<dyn std::error::Error>> {
+    let nbtiles = options.cubetiles.max(1);
+    let facesize = options.facesize.unwrap_or(img.width() / 4);
+    //面边长需能被切片数整除
+    let facesize = (facesize / nbtiles).max(1) * nbtiles;
+    let tilesize = facesize / nbtiles;
+    let cube_folder = folder.join("cube");
+    fs::create_dir_all(&cube_folder)?;
+
+    let names: Vec<String> = FACES
+        .par_iter()
+        .enumerate()
+        .map(|(face, name)| -> Result<String, String> {
+            let faceimg = render_face(img, face, facesize);
+            if nbtiles == 1 {
+                let facename = format!("{}.{}", name, options.encode.format.extension());
+                let outputf = cube_folder.join(&facename);
+                println!("{}文件导出", outputf.to_str().unwrap());
+                options
+                    .encode
+                    .save(&faceimg, &outputf)
+                    .map_err(|e| e.to_string())?;
+                return Ok(facename);
+            }
+            let face_folder = cube_folder.join(name);
+            fs::create_dir_all(&face_folder).map_err(|e| e.to_string())?;
+            for i in 0..nbtiles {
+                for j in 0..nbtiles {
+                    let region = faceimg.view(j * tilesize, i * tilesize, tilesize, tilesize);
+                    let outputf = face_folder.join(options.tile_name(i + 1, j + 1));
+                    println!("{}文件导出", outputf.to_str().unwrap());
+                    options
+                        .encode
+                        .save(&region.to_image(), &outputf)
+                        .map_err(|e| e.to_string())?;
+                }
+            }
+            Ok(name.to_string())
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(PCubemap {
+        facesize,
+        nbtiles,
+        path: format!("{}/cube", relpath),
+        faces: PCubeFaces {
+            left: names[0].clone(),
+            front: names[1].clone(),
+            right: names[2].clone(),
+            back: names[3].clone(),
+            top: names[4].clone(),
+            bottom: names[5].clone(),
+        },
+        template: if nbtiles > 1 {
+            Some(options.template.clone())
+        } else {
+            None
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use crate::tile::{PPanoData, TileMode};
+    use std::f64::consts::PI;
+
+    //红、蓝通道为yaw的正弦、余弦，绿色通道为纬度
+    fn panorama() -> RgbImage {
+        RgbImage::from_fn(360, 180, |x, y| {
+            let yaw = ((x as f64 + 0.5) / 360.0 - 0.5) * 2.0 * PI;
+            Rgb([
+                (128.0 + 127.0 * yaw.sin()).round() as u8,
+                (y * 255 / 179) as u8,
+                (128.0 + 127.0 * yaw.cos()).round() as u8,
+            ])
+        })
+    }
+
+    fn options(facesize: Option<u32>, cubetiles: u32) -> TileOptions {
+        TileOptions {
+            cubemap: true,
+            facesize,
+            cubetiles,
+            ..testutil::tile_options(TileMode::Edge, 1)
+        }
+    }
+
+    fn close(pixel: Rgb<u8>, expected: [u8; 3]) -> bool {
+        pixel
+            .0
+            .iter()
+            .zip(expected)
+            .all(|(a, b)| a.abs_diff(b) <= 3)
+    }
+
+    #[test]
+    fn face_centers_look_along_axes() {
+        let img = panorama();
+        let padded = PaddedImage::new(&img, &PPanoData::centered(360, 180));
+        //边长为奇数时中心像素正对面的中心
+        let center = |face: usize| *render_face(&padded, face, 33).get_pixel(16, 16);
+        //left、front、right、back的yaw依次为-90、0、90、180度
+        assert!(close(center(0), [1, 128, 128]), "{:?}", center(0));
+        assert!(close(center(1), [128, 128, 255]), "{:?}", center(1));
+        assert!(close(center(2), [255, 128, 128]), "{:?}", center(2));
+        assert!(close(center(3), [128, 128, 1]), "{:?}", center(3));
+        assert_eq!(center(4).0[1], 0);
+        assert_eq!(center(5).0[1], 255);
+    }
+
+    #[test]
+    fn facesize_rounds_down_to_tiles() {
+        let img = panorama();
+        let padded = PaddedImage::new(&img, &PPanoData::centered(360, 180));
+        let folder = testutil::temp_folder("cubemap");
+
+        let cubemap = clip_cubemap(&padded, &options(Some(100), 3), &folder, "g/p").unwrap();
+        assert_eq!((cubemap.facesize, cubemap.nbtiles), (99, 3));
+        assert_eq!(cubemap.path, "g/p/cube");
+        assert_eq!(cubemap.faces.top, "top");
+        let tile = image::open(folder.join("cube/top/r3-c3.png")).unwrap();
+        assert_eq!((tile.width(), tile.height()), (33, 33));
+
+        //默认边长为全景图宽度的1/4，不切片时每个面一张图
+        let cubemap = clip_cubemap(&padded, &options(None, 1), &folder, "g/p").unwrap();
+        assert_eq!((cubemap.facesize, cubemap.nbtiles), (90, 1));
+        assert_eq!(cubemap.faces.front, "front.png");
+        assert!(cubemap.template.is_none());
+        //边长小于切片数时每个切片1像素
+        let cubemap = clip_cubemap(&padded, &options(Some(2), 3), &folder, "g/p").unwrap();
+        assert_eq!(cubemap.facesize, 3);
+        let _ = fs::remove_dir_all(&folder);
+    }
+}
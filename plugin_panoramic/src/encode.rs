@@ -0,0 +1,251 @@
+use clap::ValueEnum;
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::{ExtendedColorType, ImageEncoder, RgbImage};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::BufWriter;
+use std::path::Path;
+
+//AVIF编码速度，1最慢压缩率最高，10最快
+const AVIF_SPEED: u8 = 6;
+
+/// 切片、缩略图及单张全景图的输出格式
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Jpeg,
+    /// 有损WebP
+    Webp,
+    Avif,
+    /// 无损PNG
+    Png,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Avif => "avif",
+            OutputFormat::Png => "png",
+        }
+    }
+
+    /// 未指定质量时的默认值，JPEG与旧版一致
+    pub fn default_quality(&self) -> u8 {
+        match self {
+            OutputFormat::Jpeg => 75,
+            OutputFormat::Webp => 80,
+            OutputFormat::Avif => 70,
+            OutputFormat::Png => 100,
+        }
+    }
+
+    /// 扩展名是否对应该格式，不区分大小写
+    pub fn matches_extension(&self, ext: &str) -> bool {
+        let ext = ext.to_ascii_lowercase();
+        match self {
+            OutputFormat::Jpeg => ext == "jpg" || ext == "jpeg",
+            OutputFormat::Png => ext == "png",
+            OutputFormat::Webp => ext == "webp",
+            OutputFormat::Avif => ext == "avif",
+        }
+    }
+}
+
+/// 图片编码参数
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct EncodeOptions {
+    pub format: OutputFormat,
+    /// 1-100，PNG忽略
+    pub quality: u8,
+    /// 渐进式JPEG，其他格式忽略
+    pub progressive: bool,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            format: OutputFormat::Jpeg,
+            quality: OutputFormat::Jpeg.default_quality(),
+            progressive: false,
+        }
+    }
+}
+
+impl EncodeOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=100).contains(&self.quality) {
+            return Err(format!("输出质量{}需在1至100之间", self.quality));
+        }
+        Ok(())
+    }
+
+    /// 将文件名或模板的扩展名替换为输出格式的扩展名，扩展名已对应该格式时保持不变
+    pub fn with_extension(&self, name: &str) -> String {
+        let stem = match name.rsplit_once('.') {
+            Some((_, ext)) if self.format.matches_extension(ext) => return name.to_string(),
+            Some((stem, ext))
+                if !ext.is_empty() && ext.chars().all(|c| c.is_ascii_alphanumeric()) =>
+            {
+                stem
+            }
+            _ => name,
+        };
+        format!("{}.{}", stem, self.format.extension())
+    }
+
+    pub fn save(&self, img: &RgbImage, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let (width, height) = img.dimensions();
+        match self.format {
+            OutputFormat::Jpeg if self.progressive => {
+                let mut encoder = jpeg_encoder::Encoder::new_file(path, self.quality)?;
+                encoder.set_progressive(true);
+                encoder.encode(
+                    img.as_raw(),
+                    width.try_into()?,
+                    height.try_into()?,
+                    jpeg_encoder::ColorType::Rgb,
+                )?;
+            }
+            OutputFormat::Jpeg => {
+                let file = BufWriter::new(fs::File::create(path)?);
+                JpegEncoder::new_with_quality(file, self.quality).write_image(
+                    img.as_raw(),
+                    width,
+                    height,
+                    ExtendedColorType::Rgb8,
+                )?;
+            }
+            OutputFormat::Webp => {
+                let data = webp::Encoder::from_rgb(img.as_raw(), width, height)
+                    .encode(self.quality as f32);
+                fs::write(path, &*data)?;
+            }
+            OutputFormat::Avif => {
+                let file = BufWriter::new(fs::File::create(path)?);
+                AvifEncoder::new_with_speed_quality(file, AVIF_SPEED, self.quality).write_image(
+                    img.as_raw(),
+                    width,
+                    height,
+                    ExtendedColorType::Rgb8,
+                )?;
+            }
+            OutputFormat::Png => img.save_with_format(path, image::ImageFormat::Png)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use image::{ImageFormat, Rgb};
+
+    fn options(format: OutputFormat, progressive: bool) -> EncodeOptions {
+        EncodeOptions {
+            format,
+            quality: format.default_quality(),
+            progressive,
+        }
+    }
+
+    #[test]
+    fn keeps_matching_extension() {
+        let jpeg = options(OutputFormat::Jpeg, false);
+        assert_eq!(jpeg.with_extension("a_low.jpg"), "a_low.jpg");
+        assert_eq!(jpeg.with_extension("a_low.JPG"), "a_low.JPG");
+        assert_eq!(jpeg.with_extension("a.jpeg"), "a.jpeg");
+        assert_eq!(
+            options(OutputFormat::Png, false).with_extension("a.PNG"),
+            "a.PNG"
+        );
+    }
+
+    #[test]
+    fn replaces_extension() {
+        let webp = options(OutputFormat::Webp, false);
+        assert_eq!(webp.with_extension("a_low.JPG"), "a_low.webp");
+        assert_eq!(webp.with_extension("dir.v2/a.jpeg"), "dir.v2/a.webp");
+        assert_eq!(
+            options(OutputFormat::Avif, false).with_extension("a.png"),
+            "a.avif"
+        );
+    }
+
+    #[test]
+    fn appends_missing_extension() {
+        let png = options(OutputFormat::Png, false);
+        assert_eq!(png.with_extension("a"), "a.png");
+        assert_eq!(png.with_extension("a."), "a..png");
+        //切片模板的后缀不是扩展名，保留原样
+        assert_eq!(png.with_extension("r{row}.c{col}"), "r{row}.c{col}.png");
+        assert_eq!(
+            options(OutputFormat::Webp, false).with_extension("{level}/r{row}.c{col}"),
+            "{level}/r{row}.c{col}.webp"
+        );
+    }
+
+    fn save_and_read(encode: EncodeOptions, name: &str) -> (Vec<u8>, Option<ImageFormat>) {
+        let folder = testutil::temp_folder(&format!("encode-{}", name));
+        let path = folder.join(name);
+        let img = RgbImage::from_pixel(64, 32, Rgb([200, 100, 50]));
+        encode.save(&img, &path).unwrap();
+        let data = fs::read(&path).unwrap();
+        let _ = fs::remove_dir_all(&folder);
+        let reader = image::io::Reader::new(std::io::Cursor::new(&data))
+            .with_guessed_format()
+            .unwrap();
+        let format = reader.format();
+        let decoded = reader.decode().unwrap().to_rgb8();
+        assert_eq!(decoded.dimensions(), (64, 32));
+        let pixel = decoded.get_pixel(32, 16);
+        for (v, e) in pixel.0.iter().zip([200u8, 100, 50]) {
+            assert!(v.abs_diff(e) < 12, "{:?}", pixel);
+        }
+        (data, format)
+    }
+
+    /// JPEG中是否包含指定的帧起始标记
+    fn has_marker(data: &[u8], marker: u8) -> bool {
+        data.windows(2).any(|w| w == [0xFF, marker])
+    }
+
+    #[test]
+    fn webp_round_trip() {
+        let (data, format) = save_and_read(options(OutputFormat::Webp, false), "a.webp");
+        assert_eq!(format, Some(ImageFormat::WebP));
+        assert_eq!(&data[8..12], b"WEBP");
+    }
+
+    //默认特性不含AVIF解码器，只检查编码结果
+    #[test]
+    fn avif_encodes() {
+        let folder = testutil::temp_folder("encode-avif");
+        let path = folder.join("a.avif");
+        let img = RgbImage::from_pixel(64, 32, Rgb([200, 100, 50]));
+        options(OutputFormat::Avif, false)
+            .save(&img, &path)
+            .unwrap();
+        let data = fs::read(&path).unwrap();
+        let _ = fs::remove_dir_all(&folder);
+        assert_eq!(&data[4..12], b"ftypavif");
+        //ispe记录图像宽高，位于版本及标志位之后
+        let ispe = data.windows(4).position(|w| w == b"ispe").unwrap();
+        assert_eq!(&data[ispe + 8..ispe + 16], [0, 0, 0, 64, 0, 0, 0, 32]);
+    }
+
+    #[test]
+    fn progressive_jpeg_round_trip() {
+        let (data, format) = save_and_read(options(OutputFormat::Jpeg, true), "p.jpg");
+        assert_eq!(format, Some(ImageFormat::Jpeg));
+        //SOF2为渐进式，SOF0为基线
+        assert!(has_marker(&data, 0xC2) && !has_marker(&data, 0xC0));
+        let (data, format) = save_and_read(options(OutputFormat::Jpeg, false), "b.jpg");
+        assert_eq!(format, Some(ImageFormat::Jpeg));
+        assert!(has_marker(&data, 0xC0) && !has_marker(&data, 0xC2));
+    }
+}
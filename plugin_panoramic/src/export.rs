@@ -0,0 +1,246 @@
+use crate::triangulate::PObject;
+use crate::{PGroup, PImage};
+use geojson::{Feature, FeatureCollection, Geometry, JsonObject, JsonValue, Value};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+fn properties(group: &PGroup, image: &PImage) -> JsonObject {
+    let mut properties = JsonObject::new();
+    properties.insert("id".to_string(), JsonValue::from(image.id.as_str()));
+    properties.insert("group_id".to_string(), JsonValue::from(group.id.as_str()));
+    properties.insert(
+        "name".to_string(),
+        JsonValue::from(image.imagename.as_str()),
+    );
+    properties.insert("altitude".to_string(), JsonValue::from(image.height));
+    properties.insert("heading".to_string(), JsonValue::from(image.heading));
+    if let Some(projected) = &image.projected {
+        properties.insert("easting".to_string(), JsonValue::from(projected.easting));
+        properties.insert("northing".to_string(), JsonValue::from(projected.northing));
+        properties.insert("zone".to_string(), JsonValue::from(projected.zone));
+    }
+    properties.insert(
+        "thumbnail".to_string(),
+        JsonValue::from(image.thumbnail.as_str()),
+    );
+    properties
+}
+
+/// 导出全景图拍摄点为GeoJSON点要素集合，无位置信息的全景图不导出
+pub fn write_geojson(groups: &[PGroup], path: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+    let features: Vec<Feature> = groups
+        .iter()
+        .flat_map(|group| group.flatten())
+        .flat_map(|group| {
+            group.images.iter().filter_map(move |image| {
+                let lonlat = image.position()?;
+                Some(Feature {
+                    bbox: None,
+                    geometry: Some(Geometry::new(Value::Point(lonlat.clone()))),
+                    id: None,
+                    properties: Some(properties(group, image)),
+                    foreign_members: None,
+                })
+            })
+        })
+        .collect();
+    let count = features.len();
+    fs::write(path, FeatureCollection::from_iter(features).to_string())?;
+    Ok(count)
+}
+
+/// 导出交会得到的目标为GeoJSON点要素集合
+pub fn write_objects(
+    objects: &[PObject],
+    path: &Path,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let features: Vec<Feature> = objects
+        .iter()
+        .map(|object| {
+            let mut properties = JsonObject::new();
+            properties.insert(
+                "group_id".to_string(),
+                JsonValue::from(object.group.as_str()),
+            );
+            properties.insert(
+                "type".to_string(),
+                JsonValue::from(object.marker_type.as_str()),
+            );
+            properties.insert("label".to_string(), JsonValue::from(object.label.as_str()));
+            properties.insert("height".to_string(), JsonValue::from(object.height));
+            properties.insert("residual".to_string(), JsonValue::from(object.residual));
+            properties.insert("images".to_string(), JsonValue::from(object.images.clone()));
+            Feature {
+                bbox: None,
+                geometry: Some(Geometry::new(Value::Point(object.lonlat.clone()))),
+                id: None,
+                properties: Some(properties),
+                foreign_members: None,
+            }
+        })
+        .collect();
+    let count = features.len();
+    fs::write(path, FeatureCollection::from_iter(features).to_string())?;
+    Ok(count)
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// 导出全景图拍摄点为KML，每个分组一个Folder，子分组嵌套在其中
+pub fn write_kml(groups: &[PGroup], path: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+    let mut kml = String::new();
+    let mut count = 0;
+    kml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    kml.push_str("<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n<Document>\n");
+    for group in groups {
+        count += write_kml_folder(&mut kml, group)?;
+    }
+    kml.push_str("</Document>\n</kml>\n");
+    fs::write(path, kml)?;
+    Ok(count)
+}
+
+fn write_kml_folder(kml: &mut String, group: &PGroup) -> Result<usize, std::fmt::Error> {
+    let mut count = 0;
+    writeln!(kml, "<Folder><name>{}</name>", escape_xml(&group.name))?;
+    for image in &group.images {
+        let Some(lonlat) = image.position() else {
+            continue;
+        };
+        count += 1;
+        writeln!(
+            kml,
+            "<Placemark><name>{}</name>",
+            escape_xml(&image.imagename)
+        )?;
+        kml.push_str("<ExtendedData>");
+        for (key, value) in properties(group, image) {
+            let value = match value {
+                JsonValue::String(v) => v,
+                JsonValue::Null => continue,
+                v => v.to_string(),
+            };
+            write!(
+                kml,
+                "<Data name=\"{}\"><value>{}</value></Data>",
+                key,
+                escape_xml(&value)
+            )?;
+        }
+        kml.push_str("</ExtendedData>\n");
+        writeln!(
+            kml,
+            "<Point><coordinates>{},{},{}</coordinates></Point></Placemark>",
+            lonlat[0],
+            lonlat[1],
+            lonlat.get(2).copied().unwrap_or(0.0)
+        )?;
+    }
+    for child in &group.groups {
+        count += write_kml_folder(kml, child)?;
+    }
+    kml.push_str("</Folder>\n");
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    fn image(group: &str, name: &str, lonlat: Option<[f64; 3]>, height: Option<f64>) -> PImage {
+        PImage {
+            lonlat: lonlat.map(Vec::from),
+            height,
+            heading: Some(90.0),
+            ..testutil::image(&format!("{}/{}", group, name))
+        }
+    }
+
+    fn group(id: &str, images: Vec<PImage>, groups: Vec<PGroup>) -> PGroup {
+        PGroup {
+            id: id.to_string(),
+            name: id.rsplit('/').next().unwrap().to_string(),
+            images,
+            groups,
+        }
+    }
+
+    /// 两级分组，其中各有一张无位置的全景图
+    fn fixture() -> Vec<PGroup> {
+        let child = group(
+            "a/b&c",
+            vec![
+                image("a/b&c", "q<1>", Some([116.4, 39.9, 0.0]), Some(0.0)),
+                image("a/b&c", "r", Some([0.0, 0.0, 0.0]), None),
+            ],
+            vec![],
+        );
+        vec![group(
+            "a",
+            vec![
+                image("a", "p", Some([116.3, 39.8, 52.5]), Some(52.5)),
+                image("a", "s", None, None),
+            ],
+            vec![child],
+        )]
+    }
+
+    #[test]
+    fn geojson_skips_images_without_position() {
+        let folder = testutil::temp_folder("export-geojson");
+        let path = folder.join("qindex.geojson");
+        assert_eq!(write_geojson(&fixture(), &path).unwrap(), 2);
+        let collection: FeatureCollection = fs::read_to_string(&path).unwrap().parse().unwrap();
+        let _ = fs::remove_dir_all(&folder);
+        let properties: Vec<&JsonObject> = collection
+            .features
+            .iter()
+            .map(|f| f.properties.as_ref().unwrap())
+            .collect();
+        assert_eq!(properties[0]["id"], "a/p");
+        assert_eq!(properties[0]["group_id"], "a");
+        assert_eq!(properties[0]["name"], "p");
+        assert_eq!(properties[0]["altitude"], 52.5);
+        assert_eq!(properties[0]["heading"], 90.0);
+        assert_eq!(properties[0]["thumbnail"], "a/p/p_low.JPG");
+        assert_eq!(properties[1]["id"], "a/b&c/q<1>");
+        assert_eq!(properties[1]["group_id"], "a/b&c");
+        //海拔为0时仍然输出
+        assert_eq!(properties[1]["altitude"], 0.0);
+        assert_eq!(
+            collection.features[1].geometry.as_ref().unwrap().value,
+            Value::Point(vec![116.4, 39.9, 0.0])
+        );
+    }
+
+    #[test]
+    fn kml_nests_folders_and_escapes_names() {
+        let folder = testutil::temp_folder("export-kml");
+        let path = folder.join("qindex.kml");
+        assert_eq!(write_kml(&fixture(), &path).unwrap(), 2);
+        let kml = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_dir_all(&folder);
+        let folder = kml.find("<Folder><name>a</name>").unwrap();
+        let child = kml.find("<Folder><name>b&amp;c</name>").unwrap();
+        let p = kml.find("<Placemark><name>p</name>").unwrap();
+        let q = kml.find("<Placemark><name>q&lt;1&gt;</name>").unwrap();
+        //子分组的Folder嵌套在父分组中
+        assert!(folder < p && p < child && child < q);
+        assert_eq!(kml.matches("<Folder>").count(), 2);
+        assert_eq!(kml.matches("</Folder>").count(), 2);
+        assert!(kml.rfind("</Folder>\n</Folder>").unwrap() > q);
+        assert!(!kml.contains("<name>r</name>") && !kml.contains("<name>s</name>"));
+        assert!(kml.contains("<Data name=\"group_id\"><value>a/b&amp;c</value></Data>"));
+        assert!(kml.contains("<Data name=\"altitude\"><value>52.5</value></Data>"));
+        assert!(kml.contains("<Data name=\"thumbnail\"><value>a/p/p_low.JPG</value></Data>"));
+        assert!(kml.contains("<coordinates>116.3,39.8,52.5</coordinates>"));
+        assert!(!kml.contains("q<1>") && !kml.contains("b&c"));
+    }
+}
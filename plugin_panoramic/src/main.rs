@@ -1,18 +1,54 @@
 use clap::builder::Str;
-use clap::{Error, Parser};
+use clap::{Error, Parser, Subcommand};
 use image::imageops::thumbnail;
-use image::{io::Reader as ImageReader, GenericImage, GenericImageView, ImageError};
-use image::{open, RgbImage};
+use image::{io::Reader as ImageReader, ImageError};
 use serde_json::Number;
 use std::fs::{self, create_dir_all};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
 //use error_chain::ChainedError;
-use glob::{glob_with, MatchOptions};
+use annotation::PMarker;
+use coord::CoordSystem;
+use cubemap::PCubemap;
+use encode::{EncodeOptions, OutputFormat};
+use manifest::{Manifest, ManifestEntry};
+use metadata::CaptureTime;
+use preview::{PPreview, PreviewFilter, PreviewSize};
+use projection::{PProjected, Projection};
 use rayon::prelude::*;
+use report::{BuildReport, Reporter};
 use serde::{Deserialize, Serialize};
+use source::{OutputFolders, ToneMap};
+use tile::{PLevel, PPanoData, SmallMode, TileMode, TileOptions};
+use tour::{PLink, TourNode};
+use track::{LocationSource, Track};
 //use serde_json::Result;
 
+mod annotation;
+mod budget;
+mod check;
+mod coord;
+mod cubemap;
+mod encode;
+mod export;
+mod geodesy;
+mod manifest;
+mod metadata;
+mod preview;
+mod projection;
+mod report;
+mod snapshot;
+mod source;
+mod sphere;
+#[cfg(test)]
+mod testutil;
+mod tile;
+mod tour;
+mod track;
+mod triangulate;
+mod xmp;
+
 // #[derive(Serialize, Deserialize)]
 // pub struct PIndex {
 //     /// 相比json模块，我们可以少些很多数据类型不同代理的问题。
@@ -23,21 +59,84 @@ use serde::{Deserialize, Serialize};
 
 const MIN_SIZE: u32 = 5000;
 const THUMBNAIL_WIDTH: u32 = 1024;
-const THUMBNAIL_HEIGHT: u32 = 512;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PGroup {
+    /// 分组id，为相对于输入目录的路径，以/分隔
+    id: String,
     name: String,
     images: Vec<PImage>,
+    /// 子文件夹对应的子分组
+    groups: Vec<PGroup>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl PGroup {
+    /// 深度优先列出当前分组及全部子分组
+    fn flatten(&self) -> Vec<&PGroup> {
+        let mut groups = vec![self];
+        for group in &self.groups {
+            groups.extend(group.flatten());
+        }
+        groups
+    }
+
+    /// 深度优先访问当前分组及全部子分组
+    fn visit_mut(&mut self, f: &mut dyn FnMut(&mut PGroup)) {
+        f(self);
+        for group in &mut self.groups {
+            group.visit_mut(f);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PImage {
+    /// 全景图id，由分组名与图片名组成
+    id: String,
     imagename: String,
     lonlat: Option<Vec<f64>>,
+    /// 位置来源，exif或按拍摄时间插值的轨迹，无位置时为空
+    locationsource: Option<LocationSource>,
+    /// exif中的拍摄时间，用于按轨迹定位
+    capturetime: Option<CaptureTime>,
+    /// 拍摄点海拔（米），没有海拔时为空，lonlat中对应的值为0
     height: Option<f64>,
+    /// 投影坐标，指定--projection时输出
+    projected: Option<PProjected>,
+    /// 全景图中心的真北方位角，单位度
+    heading: Option<f64>,
+    /// 全景图经度偏移，单位弧度，全景图经度加上该值即为真北方位角
     longitudeoffset: Option<f64>,
     usetile: bool,
+    /// 多级切片信息，按分辨率从高到低排列
+    levels: Vec<PLevel>,
+    /// 非完整2:1全景在补齐画布中的位置
+    panodata: Option<PPanoData>,
+    /// 未切片时输出的单张全景图，相对于输出根目录
+    panorama: Option<String>,
+    /// 立方体贴图
+    cubemap: Option<PCubemap>,
+    /// 缩略图，相对于输出根目录
+    thumbnail: String,
+    /// 包括缩略图在内的各尺寸预览图
+    previews: Vec<PPreview>,
+    /// 切片、缩略图及单张全景图的输出格式
+    format: OutputFormat,
+    /// 输出图片的扩展名，不含点
+    extension: String,
+    /// 手动标注点
+    markers: Vec<PMarker>,
+    /// 与邻近全景图的漫游连接
+    links: Vec<PLink>,
+}
+
+impl PImage {
+    /// 有效的拍摄位置，exif缺失时经纬度为0，视为无位置
+    pub fn position(&self) -> Option<&Vec<f64>> {
+        self.lonlat
+            .as_ref()
+            .filter(|lonlat| lonlat.len() >= 2 && (lonlat[0] != 0.0 || lonlat[1] != 0.0))
+    }
 }
 
 /// Search for a pattern in a file and display the lines that contain it.
@@ -47,234 +146,881 @@ struct Cli {
     /// 输出路径
     #[arg(short, long)]
     output: Option<std::path::PathBuf>,
-    /// 输入路径，注意文件夹的结构，路径中需至少包含一个子文件夹用于分组
+    /// 输入路径，注意文件夹的结构，路径中需至少包含一个子文件夹用于分组，子文件夹可以任意嵌套
     #[arg(short, long)]
     input: Option<std::path::PathBuf>,
+    /// 切片金字塔层级数，1表示只输出原始分辨率，每多一级分辨率减半
+    #[arg(short, long, default_value_t = 1)]
+    #[serde(default = "default_levels")]
+    levels: u32,
+    /// 切片行数，仅指定行数时列数为其2倍
+    #[arg(long)]
+    rows: Option<u32>,
+    /// 切片列数，仅指定列数时行数为其一半
+    #[arg(long)]
+    cols: Option<u32>,
+    /// 目标切片像素大小，未指定行列数时按此推算行列数
+    #[arg(long)]
+    tilesize: Option<u32>,
+    /// 切片文件名模板，{row}、{col}分别替换为从1开始的行号和列号
+    #[arg(long, default_value = tile::DEFAULT_TEMPLATE)]
+    #[serde(default = "default_template")]
+    template: String,
+    /// 宽高不能被行列数整除时的处理方式，edge和resample模式不丢失像素
+    #[arg(short, long, value_enum, default_value_t = TileMode::Truncate)]
+    #[serde(default)]
+    mode: TileMode,
+    /// 宽度不足5000像素的全景图的处理方式，single输出单张全景图，upscale放大后切片
+    #[arg(short, long, value_enum, default_value_t = SmallMode::Single)]
+    #[serde(default)]
+    small: SmallMode,
+    /// 额外输出立方体贴图
+    #[arg(long)]
+    #[serde(default)]
+    cubemap: bool,
+    /// 立方体每个面的边长，默认为全景图宽度的1/4
+    #[arg(long)]
+    facesize: Option<u32>,
+    /// 立方体每个面每边的切片数，1表示不切片
+    #[arg(long, default_value_t = 1)]
+    #[serde(default = "default_cubetiles")]
+    cubetiles: u32,
+    /// 磁偏角，单位度，东偏为正，用于将磁北方向修正为真北
+    #[arg(long, allow_negative_numbers = true)]
+    declination: Option<f64>,
+    /// GPX或NMEA轨迹文件，exif中没有GPS位置的全景图按拍摄时间在轨迹中插值位置
+    #[arg(long)]
+    track: Option<std::path::PathBuf>,
+    /// 相机时钟比实际时间快的秒数，按轨迹插值前从拍摄时间中减去
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    #[serde(default)]
+    clock_offset: f64,
+    /// 相机时钟的时区，如+08:00，未指定时使用exif的OffsetTimeOriginal，均无时按UTC
+    #[arg(long, allow_hyphen_values = true)]
+    timezone: Option<String>,
+    /// 插值所用相邻轨迹点的最大时间间隔（秒），间隔更大时视为轨迹中断，不定位
+    #[arg(long, default_value_t = 300.0)]
+    #[serde(default = "default_track_max_gap")]
+    track_max_gap: f64,
+    /// 漫游连接的最大距离，单位米，指定后为同一分组内的全景图生成漫游连接
+    #[arg(long)]
+    link_distance: Option<f64>,
+    /// 每张全景图最多连接的邻近全景图数量，指定后为同一分组内的全景图生成漫游连接
+    #[arg(long)]
+    link_nearest: Option<usize>,
+    /// 额外导出KML格式的全景图拍摄点
+    #[arg(long)]
+    #[serde(default)]
+    kml: bool,
+    /// 输出坐标系，作用于qindex.json及导出的拍摄点，高德地图使用gcj02，百度地图使用bd09
+    #[arg(long, value_enum, default_value_t = CoordSystem::Wgs84)]
+    #[serde(default)]
+    coordsys: CoordSystem,
+    /// 额外输出投影坐标，gk3、gk6为CGCS2000高斯-克吕格3度带、6度带，utm为WGS84 UTM
+    #[arg(long, value_enum)]
+    projection: Option<Projection>,
+    /// 投影带号，未指定时按全部拍摄点的平均经度自动选择
+    #[arg(long)]
+    zone: Option<u32>,
+    /// 忽略编译清单，全部重新编译
+    #[arg(long)]
+    #[serde(default)]
+    force: bool,
+    /// 只检查输入目录，不写出任何文件，检查报告以JSON输出到标准输出
+    #[arg(long)]
+    #[serde(default)]
+    check: bool,
+    /// 切片、缩略图及单张全景图的输出格式，切片模板的扩展名随之替换
+    #[arg(long, value_enum, default_value_t = OutputFormat::Jpeg)]
+    #[serde(default)]
+    format: OutputFormat,
+    /// 输出质量，1-100，默认JPEG为75、WebP为80、AVIF为70
+    #[arg(long)]
+    quality: Option<u8>,
+    /// 输出渐进式JPEG
+    #[arg(long)]
+    #[serde(default)]
+    progressive: bool,
+    /// 缩放缩略图及预览图使用的滤波器
+    #[arg(long, value_enum, default_value_t = PreviewFilter::Lanczos3)]
+    #[serde(default)]
+    preview_filter: PreviewFilter,
+    /// 额外输出的预览图，格式为名称:宽度，可多次指定，如--preview icon:256 --preview placeholder:2048
+    #[arg(long = "preview", value_parser = preview::parse_preview)]
+    #[serde(default)]
+    previews: Vec<PreviewSize>,
+    /// 16位及浮点源文件转为8位的方式，scale按位深线性缩放，stretch按亮度分位拉伸
+    #[arg(long, value_enum, default_value_t = ToneMap::Scale)]
+    #[serde(default)]
+    tonemap: ToneMap,
+    /// 同时编译的全景图占用内存的上限（MB），全部分组共享，按源图尺寸及切片缓冲估算，单张超过上限时单独编译
+    #[arg(long)]
+    #[serde(default)]
+    memory_budget: Option<u64>,
+    #[command(subcommand)]
+    #[serde(default)]
+    command: Option<Command>,
+}
+
+/// 子命令，未指定时编译输入目录
+#[derive(Subcommand, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Command {
+    /// 从全景图渲染指定朝向及视场角的透视快照，或为每个标注点渲染快照
+    Snapshot(snapshot::SnapshotArgs),
+}
+
+/// 一次编译中各全景图共享的参数
+struct BuildContext<'a> {
+    args: &'a Cli,
+    tile_options: TileOptions,
+    /// 上次编译的清单
+    manifest: Manifest,
+    /// 遍历输入目录时跳过的输出目录
+    outputs: OutputFolders,
+    /// 缩略图及额外的预览图尺寸
+    previews: Vec<PreviewSize>,
+    /// 影响单张全景图输出的参数摘要
+    settings: String,
+    report: Reporter,
+    /// 补充位置用的轨迹
+    track: Option<Track>,
+    /// 相机时钟的时区，单位分钟
+    timezone: Option<i32>,
+}
+
+fn default_levels() -> u32 {
+    1
+}
+
+fn default_track_max_gap() -> f64 {
+    300.0
+}
+
+fn default_cubetiles() -> u32 {
+    1
+}
+
+fn default_template() -> String {
+    tile::DEFAULT_TEMPLATE.to_string()
 }
 
 //测试多线程导出操作，还多线程个毛线，debug是release的n倍
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let _ = clip_image_entry();
-    Ok(())
+//有全景图编译失败或检查发现问题时返回1，无法开始编译时返回2
+fn main() {
+    match clip_image_entry() {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("编译失败：{}", e);
+            std::process::exit(2);
+        }
+    }
 }
 
-pub fn clip_image_entry() -> Result<(), Box<dyn std::error::Error>> {
+pub fn clip_image_entry() -> Result<bool, Box<dyn std::error::Error>> {
     let args = Cli::parse();
-    let mut options: MatchOptions = Default::default();
-    options.case_sensitive = false;
+    run(args)
+}
+
+pub fn excute(options: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let args: Cli = serde_json::from_str(options)?;
+    if !run(args)? {
+        return Err("存在编译失败的全景图或检查问题".into());
+    }
+    Ok(())
+}
 
+/// 编译或检查输入目录，全部成功且没有检查问题时返回true
+fn run(args: Cli) -> Result<bool, Box<dyn std::error::Error>> {
+    if let Some(Command::Snapshot(snapshot_args)) = &args.command {
+        snapshot::run_snapshot(snapshot_args)?;
+        return Ok(true);
+    }
+    if args.check {
+        let report = check::check_input(
+            &args.input.clone().unwrap_or_default(),
+            &args.output.clone().unwrap_or_default(),
+        )?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(report.issues.is_empty());
+    }
+    Ok(!build(args)?.has_failures())
+}
+
+fn build(args: Cli) -> Result<BuildReport, Box<dyn std::error::Error>> {
     let mut _default_inputpath = PathBuf::new();
-    if let Some(input) = args.input {
-        _default_inputpath = input;
+    if let Some(input) = &args.input {
+        _default_inputpath = input.clone();
     }
     let mut _default_outputpath = PathBuf::new();
-    if let Some(output) = args.output {
-        _default_outputpath = output;
+    if let Some(output) = &args.output {
+        _default_outputpath = output.clone();
     }
-    let mut groups = Vec::new();
-    for entry in fs::read_dir(_default_inputpath)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() {
-            let group = clip_image_tiles(&path, &_default_outputpath).unwrap();
-            groups.push(group);
+    let encode = EncodeOptions {
+        format: args.format,
+        quality: args.quality.unwrap_or(args.format.default_quality()),
+        progressive: args.progressive,
+    };
+    encode.validate()?;
+    let tile_options = TileOptions {
+        rows: args.rows,
+        cols: args.cols,
+        tilesize: args.tilesize,
+        template: encode.with_extension(&args.template),
+        levels: args.levels,
+        mode: args.mode,
+        small: args.small,
+        cubemap: args.cubemap,
+        facesize: args.facesize,
+        cubetiles: args.cubetiles,
+        encode,
+    };
+    tile_options.validate()?;
+    //轨迹定位、漫游连接、投影及坐标转换在每次编译时对全部全景图重新计算，不计入参数摘要
+    let previews = preview::preview_sizes(&args.previews)?;
+    let timezone = args
+        .timezone
+        .as_deref()
+        .map(track::parse_timezone)
+        .transpose()?;
+    let track = match &args.track {
+        Some(path) => {
+            let track = Track::load(path)?;
+            println!("读取轨迹{}，共{}个轨迹点", path.display(), track.len());
+            Some(track)
+        }
+        None => None,
+    };
+    let settings = manifest::hash_str(&serde_json::to_string(&(
+        &tile_options,
+        args.declination,
+        args.tonemap,
+        &previews,
+        args.preview_filter,
+    ))?);
+    let manifest = Manifest::load(&_default_outputpath);
+    let ctx = BuildContext {
+        args: &args,
+        tile_options,
+        outputs: OutputFolders::new(&_default_outputpath, &manifest),
+        manifest,
+        previews,
+        settings,
+        report: Reporter::default(),
+        track,
+        timezone,
+    };
+    let mut jobs = Vec::new();
+    let mut planned = Vec::new();
+    for path in source::group_folders(&_default_inputpath, &ctx.outputs)? {
+        let id = path.file_name().unwrap().to_string_lossy().to_string();
+        if let Some(group) = plan_group(&path, &id, &_default_outputpath, &ctx, &mut jobs) {
+            planned.push(group);
+        }
+    }
+    let mut built = run_clip_jobs(&jobs, &ctx);
+    let mut new_manifest = Manifest::default();
+    let mut groups: Vec<PGroup> = planned
+        .into_iter()
+        .map(|group| finish_group(group, &mut built, &ctx, &mut new_manifest))
+        .collect();
+    //多张全景图中同名的标注点按WGS84位置交会
+    let (mut objects, failures) = triangulate::triangulate(&groups);
+    for (name, reason) in failures {
+        ctx.report.warn(&name, format!("目标无法交会：{}", reason));
+    }
+    if let Some(projection) = args.projection {
+        project_coordinates(&mut groups, projection, args.zone);
+    }
+    if args.coordsys != CoordSystem::Wgs84 {
+        convert_coordinates(&mut groups, args.coordsys);
+        for object in objects.iter_mut() {
+            let (lon, lat) = coord::convert(
+                object.lonlat[0],
+                object.lonlat[1],
+                CoordSystem::Wgs84,
+                args.coordsys,
+            );
+            object.lonlat = vec![lon, lat];
         }
     }
     let output_json = serde_json::to_string(&groups)?;
     let output_json_path = _default_outputpath.join("qindex.json");
-    let _ = fs::write(output_json_path, output_json);
+    //索引写出失败时不更新编译清单
+    fs::write(output_json_path, output_json)?;
+    let removed = manifest::remove_stale(
+        &ctx.manifest,
+        &new_manifest,
+        &_default_inputpath,
+        &_default_outputpath,
+    );
+    if removed > 0 {
+        println!("清理{}张已删除全景图的输出", removed);
+    }
+    new_manifest.save(&_default_outputpath)?;
+    let count = export::write_geojson(&groups, &_default_outputpath.join("qindex.geojson"))?;
+    println!("导出{}个全景图拍摄点到qindex.geojson", count);
+    let object_count =
+        export::write_objects(&objects, &_default_outputpath.join("qobjects.geojson"))?;
+    println!("导出{}个交会目标到qobjects.geojson", object_count);
+    if args.kml {
+        let count = export::write_kml(&groups, &_default_outputpath.join("qindex.kml"))?;
+        println!("导出{}个全景图拍摄点到qindex.kml", count);
+    }
+    let report = ctx.report.finish();
+    report.save(&_default_outputpath)?;
+    report.print_summary();
     println!("全景切片导出完成！");
-    Ok(())
+    Ok(report)
 }
 
-pub fn excute(options: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let args: Cli = serde_json::from_str(options)?;
-    let mut options: MatchOptions = Default::default();
-    options.case_sensitive = false;
+//计算投影坐标，所有全景图使用同一投影带以便在CAD中统一使用
+fn project_coordinates(groups: &mut [PGroup], projection: Projection, zone: Option<u32>) {
+    let positions: Vec<(f64, f64)> = groups
+        .iter()
+        .flat_map(|group| group.flatten())
+        .flat_map(|group| group.images.iter())
+        .filter_map(PImage::position)
+        .map(|lonlat| (lonlat[0], lonlat[1]))
+        .collect();
+    if positions.is_empty() {
+        return;
+    }
+    let zone = zone.unwrap_or_else(|| {
+        let lon = positions.iter().map(|p| p.0).sum::<f64>() / positions.len() as f64;
+        projection.zone_of(lon)
+    });
+    println!("投影坐标使用{:?}第{}带", projection, zone);
+    for group in groups.iter_mut() {
+        group.visit_mut(&mut |group| {
+            for image in group.images.iter_mut() {
+                if let Some(lonlat) = image.position() {
+                    image.projected = Some(projection.project(lonlat[0], lonlat[1], Some(zone)));
+                }
+            }
+        });
+    }
+}
 
-    let mut _default_inputpath = PathBuf::new();
-    if let Some(input) = args.input {
-        _default_inputpath = input;
+//将exif的WGS84坐标转换为输出坐标系，无位置信息的全景图保持不变
+fn convert_coordinates(groups: &mut [PGroup], coordsys: CoordSystem) {
+    for group in groups.iter_mut() {
+        group.visit_mut(&mut |group| {
+            for image in group.images.iter_mut() {
+                if let Some((lon, lat)) = image.position().map(|v| (v[0], v[1])) {
+                    let (lon, lat) = coord::convert(lon, lat, CoordSystem::Wgs84, coordsys);
+                    let lonlat = image.lonlat.as_mut().unwrap();
+                    lonlat[0] = lon;
+                    lonlat[1] = lat;
+                }
+            }
+        });
     }
-    let mut _default_outputpath = PathBuf::new();
-    if let Some(output) = args.output {
-        _default_outputpath = output;
+}
+
+//指定最大距离或邻近数量时生成漫游连接
+fn linking(args: &Cli) -> bool {
+    args.link_distance.is_some() || args.link_nearest.is_some()
+}
+
+//exif中没有位置的全景图按拍摄时间在轨迹中插值，source为源文件相对于输入目录的路径
+fn geotag_image(image: &mut PImage, source: &str, ctx: &BuildContext) {
+    let Some(track) = &ctx.track else {
+        return;
+    };
+    if image.position().is_some() {
+        return;
     }
-    let mut groups = Vec::new();
-    for entry in fs::read_dir(_default_inputpath)? {
-        let entry = entry?;
-        let path = entry.path();
+    let max_gap = ctx.args.track_max_gap;
+    match image.capturetime.map(|time| time.utc(ctx.timezone)) {
+        None => ctx.report.warn(source, "缺少拍摄时间，无法按轨迹定位"),
+        Some(time) => match track.interpolate(time - ctx.args.clock_offset, max_gap) {
+            Some(point) => {
+                image.lonlat = Some(vec![point.lon, point.lat, point.altitude.unwrap_or(0.0)]);
+                image.height = point.altitude;
+                image.locationsource = Some(LocationSource::Track);
+            }
+            None => ctx
+                .report
+                .warn(source, "拍摄时间超出轨迹的时间范围或位于轨迹中断处"),
+        },
+    }
+}
+
+//根据exif位置生成分组内的漫游连接，无位置信息的全景图不参与
+fn link_images(group: &mut PGroup, args: &Cli) {
+    let located: Vec<usize> = (0..group.images.len())
+        .filter(|&i| group.images[i].position().is_some())
+        .collect();
+    let nodes: Vec<TourNode> = located
+        .iter()
+        .map(|&i| {
+            let image = &group.images[i];
+            let lonlat = image.position().unwrap();
+            TourNode {
+                id: &image.id,
+                lon: lonlat[0],
+                lat: lonlat[1],
+                heading: image.heading,
+            }
+        })
+        .collect();
+    let links = tour::compute_links(&nodes, args.link_distance, args.link_nearest);
+    for (i, links) in located.into_iter().zip(links) {
+        group.images[i].links = links;
+    }
+}
+
+//按名称排序的子文件夹，保证分组顺序稳定
+fn subfolders(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut folders = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
         if path.is_dir() {
-            let group = clip_image_tiles(&path, &_default_outputpath).unwrap();
-            groups.push(group);
+            folders.push(path);
         }
     }
-    let output_json = serde_json::to_string(&groups)?;
-    let output_json_path = _default_outputpath.join("qindex.json");
-    let _ = fs::write(output_json_path, output_json);
-    println!("全景切片导出完成！");
-    Ok(())
+    folders.sort();
+    Ok(folders)
+}
+
+/// 遍历输入目录时规划的分组，全部分组规划完成后再统一编译全景图
+struct PlannedGroup {
+    group: PGroup,
+    images: Vec<(String, PlannedImage)>,
+    children: Vec<PlannedGroup>,
+}
+
+/// 分组中的全景图，未变化时复用上次的记录，否则为待编译任务的序号
+enum PlannedImage {
+    Reused(Box<ManifestEntry>),
+    Pending(usize),
+}
+
+/// 待编译的全景图，所有分组的任务共享内存预算
+struct ClipJob {
+    path: PathBuf,
+    key: String,
+    annotation: Option<PathBuf>,
+    groupfolder: PathBuf,
+    groupid: String,
+}
+
+//规划分组及其全部子分组，待编译的全景图加入jobs
+fn plan_group(
+    input: &Path,
+    id: &str,
+    output: &Path,
+    ctx: &BuildContext,
+    jobs: &mut Vec<ClipJob>,
+) -> Option<PlannedGroup> {
+    let files = source::list_sources(input);
+    let folders = source::group_folders(input, &ctx.outputs);
+    let children = folders.as_deref().unwrap_or_default();
+    let mut planned = match plan_image_tiles(input, files, children, id, output, ctx, jobs) {
+        Ok(planned) => planned,
+        Err(e) => {
+            ctx.report.fail(id, e);
+            return None;
+        }
+    };
+    match folders {
+        Ok(folders) => {
+            for path in folders {
+                let child_id = format!("{}/{}", id, path.file_name().unwrap().to_string_lossy());
+                if let Some(child) = plan_group(&path, &child_id, output, ctx, jobs) {
+                    planned.children.push(child);
+                }
+            }
+        }
+        Err(e) => ctx.report.fail(id, e),
+    }
+    Some(planned)
 }
 
-//裁切多张图片，保留原始图片的结构
-fn clip_image_tiles(input: &Path, output: &Path) -> Result<PGroup, Box<dyn std::error::Error>> {
+//找出未变化可复用的全景图，其余的加入待编译任务，groupid为分组相对于输入目录的路径
+fn plan_image_tiles(
+    input: &Path,
+    files: Vec<PathBuf>,
+    folders: &[PathBuf],
+    groupid: &str,
+    output: &Path,
+    ctx: &BuildContext,
+    jobs: &mut Vec<ClipJob>,
+) -> Result<PlannedGroup, Box<dyn std::error::Error>> {
     //获取文件夹名称
     let filename = input.file_name().unwrap().to_str().unwrap();
-    let cur_path = input.join("./*.jpg");
-    let mut options: MatchOptions = Default::default();
-    options.case_sensitive = false;
-    let files: Vec<_> = glob_with(cur_path.to_str().unwrap(), options)?
-        .filter_map(|x: Result<std::path::PathBuf, glob::GlobError>| x.ok())
-        .collect();
+    let (files, collided) = source::split_stem_collisions(files, folders);
+    for path in collided {
+        let key = format!(
+            "{}/{}",
+            groupid,
+            path.file_name().unwrap().to_string_lossy()
+        );
+        ctx.report
+            .fail(&key, "与同一分组中的其他全景图或子分组同名，输出目录冲突");
+    }
     if files.len() == 0 {
-        println!("文件夹无全景图")
+        println!("文件夹{}无全景图", groupid)
     }
     //文件名创建
-    let newgroupfolder = output.join(filename);
+    let newgroupfolder = output.join(groupid);
     create_dir_all(&newgroupfolder)?;
 
-    let mut _image_group = PGroup {
+    let _image_group = PGroup {
+        id: groupid.to_string(),
         name: filename.to_string(),
         images: Vec::new(),
+        groups: Vec::new(),
     };
-    let image_result: Vec<_> = files
-        .par_iter()
+    let planned: Vec<(PathBuf, String, Option<PathBuf>, Option<ManifestEntry>)> = files
+        .into_par_iter()
         .map(|path| {
-            clip_image_tile(path, &newgroupfolder)
-                .map_err(|e| path.display().to_string())
-                .unwrap()
+            let name = path.file_name().unwrap_or_default();
+            let key = format!("{}/{}", groupid, name.to_string_lossy());
+            let annotation = annotation::find_annotation(&path);
+            if !ctx.args.force {
+                if let Some(entry) =
+                    ctx.manifest
+                        .reusable(&key, &path, annotation.as_deref(), &ctx.settings, output)
+                {
+                    println!("全景图{}未变化，跳过", key);
+                    ctx.report.skipped();
+                    for warning in &entry.warnings {
+                        ctx.report.warn(&key, warning);
+                    }
+                    return (path, key, annotation, Some(entry));
+                }
+            }
+            (path, key, annotation, None)
+        })
+        .collect();
+    let images = planned
+        .into_iter()
+        .map(|(path, key, annotation, reused)| match reused {
+            Some(entry) => (key, PlannedImage::Reused(Box::new(entry))),
+            None => {
+                jobs.push(ClipJob {
+                    path,
+                    key: key.clone(),
+                    annotation,
+                    groupfolder: newgroupfolder.clone(),
+                    groupid: groupid.to_string(),
+                });
+                (key, PlannedImage::Pending(jobs.len() - 1))
+            }
+        })
+        .collect();
+    Ok(PlannedGroup {
+        group: _image_group,
+        images,
+        children: Vec::new(),
+    })
+}
+
+//编译全部待编译的全景图，指定内存预算时按估算在当前线程等待预留，不占用编译线程
+fn run_clip_jobs(jobs: &[ClipJob], ctx: &BuildContext) -> Vec<Option<(ManifestEntry, bool)>> {
+    let budget = ctx
+        .args
+        .memory_budget
+        .map(|mb| budget::MemoryBudget::new(mb * 1024 * 1024));
+    //未指定预算时不读取源文件头
+    let estimates: Vec<u64> = match budget {
+        Some(_) => jobs
+            .par_iter()
+            .map(|job| budget::estimate_bytes(&job.path, &ctx.tile_options))
+            .collect(),
+        None => vec![0; jobs.len()],
+    };
+    let results: Vec<Mutex<Option<(ManifestEntry, bool)>>> =
+        jobs.iter().map(|_| Mutex::new(None)).collect();
+    rayon::in_place_scope(|scope| {
+        for ((job, estimate), slot) in jobs.iter().zip(estimates).zip(&results) {
+            let reservation = budget.as_ref().map(|budget| budget.acquire(estimate));
+            scope.spawn(move |_| {
+                *slot.lock().unwrap() = clip_or_keep(
+                    &job.path,
+                    &job.key,
+                    job.annotation.as_deref(),
+                    &job.groupfolder,
+                    &job.groupid,
+                    ctx,
+                );
+                drop(reservation);
+            });
+        }
+    });
+    results
+        .into_iter()
+        .map(|slot| slot.into_inner().unwrap())
+        .collect()
+}
+
+//汇总分组及其全部子分组的编译结果，漫游连接只在同一分组内生成
+fn finish_group(
+    planned: PlannedGroup,
+    built: &mut [Option<(ManifestEntry, bool)>],
+    ctx: &BuildContext,
+    new_manifest: &mut Manifest,
+) -> PGroup {
+    let mut group = planned.group;
+    let entries: Vec<(String, ManifestEntry, bool)> = planned
+        .images
+        .into_iter()
+        .filter_map(|(key, image)| match image {
+            PlannedImage::Reused(entry) => Some((key, *entry, true)),
+            PlannedImage::Pending(i) => built[i].take().map(|(entry, ok)| (key, entry, ok)),
+        })
+        .collect();
+    //轨迹定位只作用于索引，编译记录中保留exif的位置
+    group.images = entries
+        .iter()
+        .filter(|(_, _, ok)| *ok)
+        .map(|(key, entry, _)| {
+            let mut image = entry.image.clone();
+            geotag_image(&mut image, key, ctx);
+            if linking(ctx.args) && image.heading.is_none() && image.position().is_some() {
+                ctx.report.warn(key, "缺少朝向，漫游连接不含箭头经度");
+            }
+            image
         })
         .collect();
-    _image_group.images = image_result;
-    Ok(_image_group)
+    new_manifest
+        .entries
+        .extend(entries.into_iter().map(|(key, entry, _)| (key, entry)));
+    if linking(ctx.args) {
+        link_images(&mut group, ctx.args);
+    }
+    for child in planned.children {
+        group
+            .groups
+            .push(finish_group(child, built, ctx, new_manifest));
+    }
+    group
+}
+
+//编译单张全景图，失败时保留上次的记录，无记录时返回None
+fn clip_or_keep(
+    path: &Path,
+    key: &str,
+    annotation: Option<&Path>,
+    newgroupfolder: &Path,
+    groupid: &str,
+    ctx: &BuildContext,
+) -> Option<(ManifestEntry, bool)> {
+    //解码器遇到损坏的文件可能panic，同样记为该全景图失败
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        clip_manifest_entry(path, annotation, newgroupfolder, groupid, ctx)
+            .map_err(|e| e.to_string())
+    }))
+    .unwrap_or_else(|panic| Err(panic_message(panic)));
+    match result {
+        Ok(entry) => {
+            ctx.report.built();
+            Some((entry, true))
+        }
+        Err(e) => {
+            ctx.report.fail(key, e);
+            //保留上次的输出不被清理，清空参数摘要使下次必须重新编译
+            let mut entry = ctx.manifest.entries.get(key)?.clone();
+            entry.settings.clear();
+            Some((entry, false))
+        }
+    }
+}
+
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "未知错误".to_string(),
+        },
+    }
 }
 
-fn clip_image_tile(input: &Path, output: &Path) -> Result<PImage, Box<dyn std::error::Error>> {
-    let org_img = open(input)?;
-    let img = org_img.into_rgb8();
+//编译单张全景图并记录源文件指纹，源文件指纹在解码时一并计算
+fn clip_manifest_entry(
+    path: &Path,
+    annotation: Option<&Path>,
+    output: &Path,
+    groupname: &str,
+    ctx: &BuildContext,
+) -> Result<ManifestEntry, Box<dyn std::error::Error>> {
+    let (image, source, warnings) = clip_image_tile(path, annotation, output, groupname, ctx)?;
+    Ok(ManifestEntry {
+        source,
+        annotation: annotation.map(manifest::stamp).transpose()?,
+        settings: ctx.settings.clone(),
+        outputs: vec![image.id.clone()],
+        image,
+        warnings,
+    })
+}
+
+fn clip_image_tile(
+    input: &Path,
+    annotation: Option<&Path>,
+    output: &Path,
+    groupname: &str,
+    ctx: &BuildContext,
+) -> Result<(PImage, manifest::SourceStamp, Vec<String>), Box<dyn std::error::Error>> {
+    let args = ctx.args;
+    let tile_options = &ctx.tile_options;
+    //解码全景图并解析exif及xmp的相关信息
+    let source::SourceImage {
+        img,
+        mut panodata,
+        exif,
+        xmp,
+        stamp,
+    } = source::load_source(input, args.tonemap)?;
     let filename = input.file_stem().unwrap().to_str().unwrap();
+    let source = format!(
+        "{}/{}",
+        groupname,
+        input.file_name().unwrap().to_string_lossy()
+    );
+    //警告写入编译报告，并记录在编译清单中
+    let mut warnings = Vec::new();
+    let mut warn = |reason: String| {
+        ctx.report.warn(&source, &reason);
+        warnings.push(reason);
+    };
+    //缺少exif时仍然切片，只是没有位置及朝向
+    let exif = match exif {
+        Ok(exif) => Some(exif),
+        Err(e) => {
+            warn(format!("无法读取exif：{}", e));
+            None
+        }
+    };
     //if args.input
     let newfolder = output.join(filename);
-    let clone_new_folder = newfolder.to_str().unwrap();
     fs::create_dir_all(&newfolder)?; //默认创建目录
-    let _width = img.width();
-    let _height = img.height();
-    let _rownum = 4; //默认8行4列
-    let _colnum = 8;
-    //按照2:1确定最终尺寸，要求宽度是8的倍速，resize性能未知,补齐到8倍数
-    //let _ratio=_width/_height;操作速度还是太慢了
-    let iswidthlong = _width >= 2 * _height;
-    let is_max = _width >= MIN_SIZE;
-
-    //默认创建缩略图
-    let thm_outputf = newfolder.join(format!("{}_low.JPG", filename));
-    println!("导出缩略图{:?}", thm_outputf);
-    let _ = open(input)?
-        .resize(THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT, image::imageops::Nearest)
-        .save(thm_outputf);
-
-    if iswidthlong && is_max {
-        let _nwidth = _width;
-        let _nheight = _width / 2;
-        let _offset_height = (_nheight - _height) / 2;
-        let mut imgbuf = RgbImage::new(_nwidth, _nheight);
-        imgbuf
-            .sub_image(0, _offset_height, _width, _height)
-            .copy_from(&img, 0, 0)?;
-        let tilesize = _nwidth / 8; //此算法会导致缺少8个像素数据的情况
-                                    // for i in 0.._rownum {
-                                    //     for j in 0.._colnum {
-                                    //         let region = imgbuf.view (
-                                    //             j*tilesize,i*tilesize,tilesize,tilesize
-                                    //         );
-                                    //         let  regionimgbuf =region.to_image();
-                                    //         let newfilename = format!("{}/row-{}-column-{}.jpg", filename, i + 1, j + 1);
-                                    //         println!("{}文件夹导出{}",&newfilename,tilesize);
-                                    //         let _=regionimgbuf.save(newfilename);
-                                    //     }
-                                    // }
-        (0.._rownum * _colnum).into_par_iter().for_each(|x| {
-            let i = x / 8;
-            let j = x % 8;
-
-            let region = imgbuf.view(j * tilesize, i * tilesize, tilesize, tilesize);
-            let regionimgbuf = region.to_image();
-            let newfilename = PathBuf::new().join(clone_new_folder).join(format!(
-                "row-{}-column-{}.jpg",
-                i + 1,
-                j + 1
-            ));
-            println!("{}文件导出", newfilename.to_str().unwrap());
-            let _ = regionimgbuf.save(newfilename);
-        });
-        //imgbuf.save("test.jpg")?;
-        //img.resize(_nwidth, _nheight,image::imageops::FilterType::Nearest).save("test.jpg")?;
+    let is_max = panodata.full_width >= MIN_SIZE;
+
+    //读取同名的手动标注文件
+    let mut _markers = Vec::new();
+    if let Some(annotation_path) = annotation {
+        let text = fs::read_to_string(annotation_path)?;
+        let (markers, errors) =
+            annotation::parse_annotations(annotation_path, &text, filename, &panodata);
+        for error in errors {
+            warn(format!("标注解析错误 {}", error));
+        }
+        _markers = markers;
+    }
+
+    let mut _levels = Vec::new();
+    let mut _panorama = None;
+
+    //默认创建缩略图及指定的预览图
+    let encode = &tile_options.encode;
+    let _previews = preview::render_previews(
+        &img,
+        &ctx.previews,
+        args.preview_filter,
+        encode,
+        &newfolder,
+        &format!("{}/{}", groupname, filename),
+        filename,
+    )?;
+
+    //补齐区域只在读取时生成，不复制整张图
+    let mut imgbuf = tile::PaddedImage::new(&img, &panodata);
+    if !panodata.is_full() {
+        println!(
+            "全景图{}不是完整的2:1全景，已补齐为{}x{}",
+            filename, panodata.full_width, panodata.full_height
+        );
+    }
+    if !is_max && tile_options.small == SmallMode::Upscale {
+        println!("全景图{}尺寸不足{}，放大后切片", filename, MIN_SIZE);
+        panodata = panodata.scaled(MIN_SIZE);
+        //标注点的像素坐标与记录的panodata一致
+        for marker in &mut _markers {
+            marker.locate(&panodata);
+        }
+        imgbuf = tile::PaddedImage::owned(
+            image::imageops::resize(
+                &img,
+                panodata.cropped_width,
+                panodata.cropped_height,
+                image::imageops::CatmullRom,
+            ),
+            &panodata,
+        );
+    }
+    if is_max || tile_options.small == SmallMode::Upscale {
+        _levels = tile::clip_pyramid(
+            &imgbuf,
+            tile_options,
+            &newfolder,
+            &format!("{}/{}", groupname, filename),
+        )?;
     } else {
-        println!("全景图{}不符合要求，暂未处理", filename)
-        //Ok(())
+        println!("全景图{}尺寸不足{}，输出单张全景图", filename, MIN_SIZE);
+        let panorama_name = encode.with_extension(&format!("{}.jpg", filename));
+        encode.save(&imgbuf.to_image(), &newfolder.join(&panorama_name))?;
+        _panorama = Some(format!("{}/{}/{}", groupname, filename, panorama_name));
+    }
+    let mut _cubemap = None;
+    if tile_options.cubemap {
+        _cubemap = Some(cubemap::clip_cubemap(
+            &imgbuf,
+            tile_options,
+            &newfolder,
+            &format!("{}/{}", groupname, filename),
+        )?);
     }
-    //
     let mut _image_info = PImage {
+        id: format!("{}/{}", groupname, filename),
         imagename: filename.to_string(),
-        height: None,
+        height: exif.as_ref().and_then(metadata::read_altitude),
+        projected: None,
+        heading: None,
         lonlat: None,
+        locationsource: None,
+        capturetime: exif.as_ref().and_then(metadata::read_capture_time),
         longitudeoffset: None,
-        usetile: true,
-    };
-    let mut _lonlat = vec![0.0f64, 0.0f64, 0.0f64];
-
-    //解析exif的相关信息
-    let file = std::fs::File::open(input)?;
-    let mut bufreader = std::io::BufReader::new(&file);
-    let exifreader = exif::Reader::new();
-    let exif = exifreader.read_from_container(&mut bufreader)?;
-
-    //AbsoluteAltitude
-    //GPSAltitude
-    //GPSLatitude
-    match exif.get_field(exif::Tag::GPSAltitude, exif::In::PRIMARY) {
-        Some(xres) => match xres.value {
-            exif::Value::Rational(ref v) if !v.is_empty() => {
-                //println!("GPSAltitude {}", v[0].to_f64())
-                _lonlat[2] = v[0].to_f64();
-            }
-            _ => eprintln!("GPSAltitude value is broken"),
+        usetile: !_levels.is_empty(),
+        levels: _levels,
+        panodata: if panodata.is_needed() {
+            Some(panodata)
+        } else {
+            None
         },
-        None => eprintln!("GPSAltitude tag is missing"),
-    }
-
-    match exif.get_field(exif::Tag::GPSLatitude, exif::In::PRIMARY) {
-        Some(xres) => match xres.value {
-            exif::Value::Rational(ref v) if !v.is_empty() => {
-                // println!(
-                //     "GPSLatitude {},{},{}",
-                //     v[0].to_f64(),
-                //     v[1].to_f64(),
-                //     v[2].to_f64()
-                // )
-                _lonlat[1] = v[0].to_f64() + v[1].to_f64() / 60.0f64 + v[2].to_f64() / 3600.0f64;
-            }
-            _ => eprintln!("GPSLatitude value is broken"),
-        },
-        None => eprintln!("GPSLatitude tag is missing"),
-    }
-
-    match exif.get_field(exif::Tag::GPSLongitude, exif::In::PRIMARY) {
-        Some(xres) => match xres.value {
-            exif::Value::Rational(ref v) if !v.is_empty() => {
-                // println!(
-                //     "GPSLongitude {},{},{}",
-                //     v[0].to_f64(),
-                //     v[1].to_f64(),
-                //     v[2].to_f64()
-                // )
-                _lonlat[0] = v[0].to_f64() + v[1].to_f64() / 60.0f64 + v[2].to_f64() / 3600.0f64;
-            }
-            _ => eprintln!("GPSLongitude value is broken"),
-        },
-        None => eprintln!("GPSLongitude tag is missing"),
+        panorama: _panorama,
+        cubemap: _cubemap,
+        thumbnail: _previews
+            .iter()
+            .find(|p| p.name == preview::THUMBNAIL_NAME)
+            .map(|p| p.path.clone())
+            .unwrap_or_default(),
+        previews: _previews,
+        format: encode.format,
+        extension: encode.format.extension().to_string(),
+        markers: _markers,
+        links: Vec::new(),
+    };
+    _image_info.lonlat = exif.as_ref().map(metadata::read_lonlat);
+    if _image_info.position().is_some() {
+        _image_info.locationsource = Some(LocationSource::Exif);
+    }
+    let (heading, heading_warning) =
+        metadata::read_heading(exif.as_ref(), xmp.as_deref(), args.declination);
+    if let Some(reason) = heading_warning {
+        warn(reason.to_string());
+    }
+    _image_info.heading = heading;
+    _image_info.longitudeoffset = _image_info.heading.map(f64::to_radians);
+    if let Some(offset) = _image_info.longitudeoffset {
+        for marker in &mut _image_info.markers {
+            marker.bearing = Some(sphere::yaw_to_bearing(marker.yaw, offset));
+        }
     }
-    _image_info.lonlat = Option::Some(_lonlat);
     // for f in exif.fields() {
     //     println!(
     //         "{} {} {}",
@@ -283,5 +1029,241 @@ fn clip_image_tile(input: &Path, output: &Path) -> Result<PImage, Box<dyn std::e
     //         f.display_value().with_unit(&exif)
     //     );
     // }
-    Ok(_image_info)
+    Ok((_image_info, stamp, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn write_image(path: &Path) {
+        create_dir_all(path.parent().unwrap()).unwrap();
+        RgbImage::from_pixel(200, 100, Rgb([40, 80, 120]))
+            .save(path)
+            .unwrap();
+    }
+
+    fn cli(input: &Path, output: &Path) -> Cli {
+        Cli::try_parse_from([
+            "pbuildtool",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+        ])
+        .unwrap()
+    }
+
+    fn build_tree(input: &Path, output: &Path) -> BuildReport {
+        build(cli(input, output)).unwrap()
+    }
+
+    fn sources(issues: &[report::PIssue]) -> Vec<&str> {
+        issues.iter().map(|issue| issue.source.as_str()).collect()
+    }
+
+    #[test]
+    fn nested_groups_use_relative_ids() {
+        let folder = testutil::temp_folder("build-nested");
+        let (input, output) = (folder.join("in"), folder.join("out"));
+        write_image(&input.join("a/p.jpg"));
+        write_image(&input.join("a/b/q.jpg"));
+        write_image(&input.join("a/b/c/r.jpg"));
+        write_image(&input.join("d/s.jpg"));
+        let report = build_tree(&input, &output);
+        assert!(report.failures.is_empty());
+        assert_eq!(report.built, 4);
+        let mut groups: Vec<PGroup> =
+            serde_json::from_str(&fs::read_to_string(output.join("qindex.json")).unwrap()).unwrap();
+        let flattened: Vec<&PGroup> = groups.iter().flat_map(PGroup::flatten).collect();
+        let ids: Vec<String> = flattened.iter().map(|g| g.id.clone()).collect();
+        assert_eq!(ids, ["a", "a/b", "a/b/c", "d"]);
+        let names: Vec<&str> = flattened.iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c", "d"]);
+        for group in &flattened {
+            assert_eq!(group.images.len(), 1);
+            let image = &group.images[0];
+            assert_eq!(image.id, format!("{}/{}", group.id, image.imagename));
+            //输出位于以分组id为路径的目录中
+            let prefix = output.join(&group.id).join(&image.imagename);
+            let panorama = image.panorama.as_ref().unwrap();
+            assert!(output.join(panorama).starts_with(&prefix));
+            assert!(output.join(panorama).is_file());
+            assert!(output.join(&image.thumbnail).starts_with(&prefix));
+            assert!(output.join(&image.thumbnail).is_file());
+            assert!(image
+                .thumbnail
+                .ends_with(&format!("/{}_low.JPG", image.imagename)));
+        }
+        let mut visited = Vec::new();
+        for group in &mut groups {
+            group.visit_mut(&mut |g| {
+                g.images.clear();
+                visited.push(g.id.clone());
+            });
+        }
+        assert_eq!(visited, ids);
+        assert!(groups
+            .iter()
+            .flat_map(PGroup::flatten)
+            .all(|g| g.images.is_empty()));
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn image_clashing_with_child_group_fails() {
+        let folder = testutil::temp_folder("build-clash");
+        let (input, output) = (folder.join("in"), folder.join("out"));
+        write_image(&input.join("g/p1.png"));
+        write_image(&input.join("g/p1/x.png"));
+        let report = build_tree(&input, &output);
+        assert_eq!(sources(&report.failures), ["g/p1.png"]);
+        assert!(output.join("g/p1/x/x.jpg").is_file());
+
+        //删除与子分组同名的全景图后再次编译，子分组的输出不受影响
+        fs::remove_file(input.join("g/p1.png")).unwrap();
+        let report = build_tree(&input, &output);
+        assert!(report.failures.is_empty());
+        assert_eq!(report.skipped, 1);
+        assert!(output.join("g/p1/x/x.jpg").is_file());
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn in_place_rebuild_skips_outputs() {
+        let folder = testutil::temp_folder("build-in-place");
+        write_image(&folder.join("g/a.png"));
+        write_image(&folder.join("g/b/c.png"));
+        let first = build_tree(&folder, &folder);
+        assert!(first.failures.is_empty());
+        assert_eq!(first.built, 2);
+        let second = build_tree(&folder, &folder);
+        assert!(second.failures.is_empty());
+        assert_eq!((second.built, second.skipped), (0, 2));
+        let groups: Vec<PGroup> =
+            serde_json::from_str(&fs::read_to_string(folder.join("qindex.json")).unwrap()).unwrap();
+        let ids: Vec<&str> = groups
+            .iter()
+            .flat_map(PGroup::flatten)
+            .map(|g| g.id.as_str())
+            .collect();
+        assert_eq!(ids, ["g", "g/b"]);
+
+        //源文件删除后，清单中记录的输出目录同样不作为分组
+        fs::remove_file(folder.join("g/a.png")).unwrap();
+        let third = build_tree(&folder, &folder);
+        assert!(third.failures.is_empty());
+        assert_eq!((third.built, third.skipped), (0, 1));
+        assert!(!folder.join("g/a").exists());
+
+        //输出目录位于输入目录中
+        let input = folder.join("nested");
+        write_image(&input.join("g/a.png"));
+        let output = input.join("out");
+        let nested = build_tree(&input, &output);
+        assert_eq!(nested.built, 1);
+        let nested = build_tree(&input, &output);
+        assert!(nested.failures.is_empty());
+        assert_eq!((nested.built, nested.skipped), (0, 1));
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn skipped_images_keep_warnings() {
+        let folder = testutil::temp_folder("build-warnings");
+        let (input, output) = (folder.join("in"), folder.join("out"));
+        write_image(&input.join("g/a.png"));
+        fs::write(input.join("g/a.txt"), "angle,10,5,pole,杆\nangle,10\n").unwrap();
+        let first = build_tree(&input, &output);
+        //缺少exif及一行标注格式错误
+        assert_eq!(first.warnings.len(), 2);
+        let second = build_tree(&input, &output);
+        assert_eq!(second.skipped, 1);
+        let reasons = |report: &BuildReport| -> Vec<String> {
+            report.warnings.iter().map(|w| w.reason.clone()).collect()
+        };
+        assert_eq!(reasons(&second), reasons(&first));
+        assert_eq!(sources(&second.warnings), ["g/a.png", "g/a.png"]);
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn index_write_error_fails_build() {
+        let folder = testutil::temp_folder("build-index");
+        let (input, output) = (folder.join("in"), folder.join("out"));
+        write_image(&input.join("g/a.png"));
+        //qindex.json被同名目录占用
+        create_dir_all(output.join("qindex.json")).unwrap();
+        assert!(build(cli(&input, &output)).is_err());
+        assert!(!output.join(manifest::MANIFEST_NAME).exists());
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn memory_budget_is_shared_by_nested_groups() {
+        let folder = testutil::temp_folder("build-budget");
+        let (input, output) = (folder.join("in"), folder.join("out"));
+        for path in ["a/p.jpg", "a/q.jpg", "a/b/r.jpg", "c/s.jpg"] {
+            write_image(&input.join(path));
+        }
+        //预算小于单张全景图的估算，全部全景图依次编译
+        let args = Cli::try_parse_from([
+            "pbuildtool",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "--memory-budget",
+            "0",
+        ])
+        .unwrap();
+        let report = build(args).unwrap();
+        assert!(report.failures.is_empty());
+        assert_eq!(report.built, 4);
+        assert!(output.join("a/b/r/r.jpg").exists());
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn heading_sets_longitude_offset() {
+        let folder = testutil::temp_folder("build-heading");
+        let (input, output) = (folder.join("in"), folder.join("out"));
+        let exif = testutil::exif_data(&testutil::direction_fields(35000, "M"));
+        testutil::write_jpeg_with_exif(&input.join("g/a.jpg"), &exif);
+        let args = Cli::try_parse_from([
+            "pbuildtool",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            output.to_str().unwrap(),
+            "--declination",
+            "15",
+        ])
+        .unwrap();
+        build(args).unwrap();
+        let groups: Vec<PGroup> =
+            serde_json::from_str(&fs::read_to_string(output.join("qindex.json")).unwrap()).unwrap();
+        let image = &groups[0].images[0];
+        let heading = image.heading.unwrap();
+        assert!((heading - 5.0).abs() < 1e-9);
+        assert_eq!(image.longitudeoffset, Some(heading.to_radians()));
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn magnetic_heading_without_declination_warns() {
+        let folder = testutil::temp_folder("build-magnetic");
+        let (input, output) = (folder.join("in"), folder.join("out"));
+        let exif = testutil::exif_data(&testutil::direction_fields(35000, "M"));
+        testutil::write_jpeg_with_exif(&input.join("g/a.jpg"), &exif);
+        let report = build_tree(&input, &output);
+        let _ = fs::remove_dir_all(&folder);
+        let warnings: Vec<(&str, &str)> = report
+            .warnings
+            .iter()
+            .map(|issue| (issue.source.as_str(), issue.reason.as_str()))
+            .collect();
+        assert!(warnings.contains(&("g/a.jpg", metadata::MAGNETIC_WITHOUT_DECLINATION)));
+    }
 }
@@ -0,0 +1,326 @@
+use crate::PImage;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+pub const MANIFEST_NAME: &str = "qbuild.json";
+
+/// 源文件的指纹
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SourceStamp {
+    pub size: u64,
+    /// 修改时间，UNIX时间戳(秒)
+    pub mtime: u64,
+    /// 文件内容的SHA-256
+    pub hash: String,
+}
+
+/// 单张全景图的编译记录
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestEntry {
+    pub source: SourceStamp,
+    /// 同名标注文件的指纹
+    pub annotation: Option<SourceStamp>,
+    /// 编译参数摘要，参数变化时需要重新编译
+    pub settings: String,
+    /// 输出目录，相对于输出根目录
+    pub outputs: Vec<String>,
+    /// 编译得到的全景图信息，不含漫游连接、投影等后处理结果
+    pub image: PImage,
+    /// 编译时的警告，如缺少exif、标注格式错误，跳过时重新写入编译报告
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+/// 编译清单，记录每个源文件及其输出，用于增量编译
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Manifest {
+    /// 键为源文件相对于输入目录的路径
+    pub entries: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// 读取输出目录中的编译清单，不存在或无法解析时返回空清单
+    pub fn load(output: &Path) -> Manifest {
+        match fs::read_to_string(output.join(MANIFEST_NAME)) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|e| {
+                eprintln!("编译清单无法解析，将全部重新编译：{}", e);
+                Manifest::default()
+            }),
+            Err(_) => Manifest::default(),
+        }
+    }
+
+    pub fn save(&self, output: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(
+            output.join(MANIFEST_NAME),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    /// 源文件、标注文件及编译参数均未变化且输出目录仍存在时返回可复用的记录，
+    /// 大小与修改时间一致时不再计算哈希
+    pub fn reusable(
+        &self,
+        key: &str,
+        source: &Path,
+        annotation: Option<&Path>,
+        settings: &str,
+        output: &Path,
+    ) -> Option<ManifestEntry> {
+        let entry = self.entries.get(key)?;
+        if entry.settings != settings
+            || !entry
+                .outputs
+                .iter()
+                .all(|folder| output.join(folder).is_dir())
+        {
+            return None;
+        }
+        let mut entry = entry.clone();
+        entry.source = restamp(&entry.source, source).ok()??;
+        entry.annotation = match (&entry.annotation, annotation) {
+            (None, None) => None,
+            (Some(stamp), Some(path)) => Some(restamp(stamp, path).ok()??),
+            _ => return None,
+        };
+        Some(entry)
+    }
+}
+
+fn metadata_stamp(path: &Path) -> std::io::Result<(u64, u64)> {
+    let metadata = fs::metadata(path)?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    Ok((metadata.len(), mtime))
+}
+
+/// 计算文件指纹
+pub fn stamp(path: &Path) -> std::io::Result<SourceStamp> {
+    let (size, mtime) = metadata_stamp(path)?;
+    Ok(SourceStamp {
+        size,
+        mtime,
+        hash: hash_file(path)?,
+    })
+}
+
+/// 按已读取的文件内容计算指纹，避免再次读取文件
+pub fn stamp_data(path: &Path, data: &[u8]) -> std::io::Result<SourceStamp> {
+    let (size, mtime) = metadata_stamp(path)?;
+    Ok(SourceStamp {
+        size,
+        mtime,
+        hash: hex(&Sha256::digest(data)),
+    })
+}
+
+/// 与已有指纹比较，内容未变化时返回更新修改时间后的指纹，内容变化时返回None
+fn restamp(old: &SourceStamp, path: &Path) -> std::io::Result<Option<SourceStamp>> {
+    let (size, mtime) = metadata_stamp(path)?;
+    if size != old.size {
+        return Ok(None);
+    }
+    if mtime == old.mtime {
+        return Ok(Some(old.clone()));
+    }
+    let hash = hash_file(path)?;
+    Ok((hash == old.hash).then_some(SourceStamp { size, mtime, hash }))
+}
+
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+pub fn hash_str(text: &str) -> String {
+    hex(&Sha256::digest(text.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 删除源文件已不存在的输出目录，返回删除的记录数；
+/// 源文件仍存在但本次未编译（如编译失败且无记录）时保留输出
+pub fn remove_stale(old: &Manifest, new: &Manifest, input: &Path, output: &Path) -> usize {
+    let claimed: Vec<&String> = new
+        .entries
+        .values()
+        .flat_map(|entry| entry.outputs.iter())
+        .collect();
+    let mut removed = 0;
+    for (key, entry) in &old.entries {
+        if new.entries.contains_key(key) || input.join(key).exists() {
+            continue;
+        }
+        removed += 1;
+        //子分组的输出位于该目录之下时同样保留
+        for folder in entry.outputs.iter().filter(|f| {
+            !claimed
+                .iter()
+                .any(|c| c == f || c.starts_with(&format!("{}/", f)))
+        }) {
+            let path = output.join(folder);
+            println!("源文件{}已删除，清理输出{}", key, path.display());
+            let _ = fs::remove_dir_all(&path);
+            //分组内已无其他输出时一并删除分组目录
+            if let Some(parent) = path.parent() {
+                let _ = fs::remove_dir(parent);
+            }
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use std::time::{Duration, SystemTime};
+
+    fn entry(source: &Path, annotation: Option<&Path>, outputs: &[&str]) -> ManifestEntry {
+        ManifestEntry {
+            source: stamp(source).unwrap(),
+            annotation: annotation.map(|path| stamp(path).unwrap()),
+            settings: "s1".to_string(),
+            outputs: outputs.iter().map(|f| f.to_string()).collect(),
+            image: testutil::image(outputs[0]),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn set_mtime(path: &Path, seconds: u64) {
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+            .unwrap();
+    }
+
+    #[test]
+    fn reusable_checks_settings_and_stamps() {
+        let folder = testutil::temp_folder("manifest-reusable");
+        let source = folder.join("a.jpg");
+        let annotation = folder.join("a.txt");
+        fs::write(&source, b"panorama").unwrap();
+        fs::write(&annotation, b"angle,0,0,pole").unwrap();
+        fs::create_dir_all(folder.join("g/a")).unwrap();
+        //按已读取的内容计算的指纹与读取文件计算的一致
+        assert_eq!(
+            stamp_data(&source, b"panorama").unwrap(),
+            stamp(&source).unwrap()
+        );
+        let mut manifest = Manifest::default();
+        manifest
+            .entries
+            .insert("g/a.jpg".to_string(), entry(&source, None, &["g/a"]));
+        assert!(manifest
+            .reusable("g/a.jpg", &source, None, "s1", &folder)
+            .is_some());
+        assert!(manifest
+            .reusable("g/a.jpg", &source, None, "s2", &folder)
+            .is_none());
+        assert!(manifest
+            .reusable("g/b.jpg", &source, None, "s1", &folder)
+            .is_none());
+
+        //只修改时间时按哈希判断，并更新记录的修改时间
+        set_mtime(&source, 1_000_000);
+        let reused = manifest
+            .reusable("g/a.jpg", &source, None, "s1", &folder)
+            .unwrap();
+        assert_eq!(reused.source.mtime, 1_000_000);
+        assert_eq!(reused.source.hash, manifest.entries["g/a.jpg"].source.hash);
+        //大小不变而内容变化
+        fs::write(&source, b"panoramb").unwrap();
+        set_mtime(&source, 2_000_000);
+        assert!(manifest
+            .reusable("g/a.jpg", &source, None, "s1", &folder)
+            .is_none());
+        fs::write(&source, b"panorama").unwrap();
+
+        //新增或删除标注文件时重新编译
+        assert!(manifest
+            .reusable("g/a.jpg", &source, Some(&annotation), "s1", &folder)
+            .is_none());
+        manifest.entries.insert(
+            "g/a.jpg".to_string(),
+            entry(&source, Some(&annotation), &["g/a"]),
+        );
+        assert!(manifest
+            .reusable("g/a.jpg", &source, Some(&annotation), "s1", &folder)
+            .is_some());
+        assert!(manifest
+            .reusable("g/a.jpg", &source, None, "s1", &folder)
+            .is_none());
+
+        //输出目录被删除时重新编译
+        fs::remove_dir_all(folder.join("g/a")).unwrap();
+        assert!(manifest
+            .reusable("g/a.jpg", &source, Some(&annotation), "s1", &folder)
+            .is_none());
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn remove_stale_keeps_claimed_and_existing_sources() {
+        let folder = testutil::temp_folder("manifest-stale");
+        let (input, output) = (folder.join("in"), folder.join("out"));
+        for dir in ["g/a", "g/b", "g/c", "g/e/x", "h/d"] {
+            fs::create_dir_all(output.join(dir)).unwrap();
+        }
+        fs::create_dir_all(input.join("g")).unwrap();
+        let source = input.join("g/c.jpg");
+        fs::write(&source, b"panorama").unwrap();
+
+        let mut old = Manifest::default();
+        let mut new = Manifest::default();
+        for (key, outputs) in [
+            ("g/a.jpg", ["g/a"]),
+            ("g/a.png", ["g/b"]),
+            ("g/c.jpg", ["g/c"]),
+            ("g/e.jpg", ["g/e"]),
+            ("h/d.jpg", ["h/d"]),
+        ] {
+            old.entries
+                .insert(key.to_string(), entry(&source, None, &outputs));
+        }
+        //g/b改由新的源文件输出
+        new.entries
+            .insert("g/a.jpg".to_string(), entry(&source, None, &["g/a"]));
+        new.entries
+            .insert("g/b.jpg".to_string(), entry(&source, None, &["g/b"]));
+        //子分组g/e的输出位于g/e之下
+        new.entries
+            .insert("g/e/x.jpg".to_string(), entry(&source, None, &["g/e/x"]));
+
+        assert_eq!(remove_stale(&old, &new, &input, &output), 3);
+        assert!(output.join("g/e/x").exists());
+        assert!(output.join("g/a").exists());
+        assert!(output.join("g/b").exists());
+        //源文件仍存在，只是本次未编译
+        assert!(output.join("g/c").exists());
+        assert!(!output.join("h/d").exists());
+        assert!(!output.join("h").exists());
+        let _ = fs::remove_dir_all(&folder);
+    }
+}
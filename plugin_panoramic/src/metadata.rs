@@ -0,0 +1,328 @@
+use crate::track;
+use crate::xmp;
+use exif::{Exif, In, Tag, Value};
+use serde::{Deserialize, Serialize};
+
+/// 度分秒转为十进制度，缺少的分、秒按0处理
+fn dms_to_degrees(v: &[exif::Rational]) -> f64 {
+    v.iter()
+        .take(3)
+        .zip([1.0f64, 60.0f64, 3600.0f64])
+        .map(|(r, unit)| r.to_f64() / unit)
+        .sum()
+}
+
+/// 读取ASCII类型的参考标记，如N/S/E/W/T/M，统一转为大写
+fn read_ref(exif: &Exif, tag: Tag) -> Option<u8> {
+    match exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(ref v) => v
+            .first()
+            .and_then(|s| s.first())
+            .map(|c| c.to_ascii_uppercase()),
+        _ => None,
+    }
+}
+
+/// 读取度分秒格式的坐标，参考标记为负方向时取负值
+fn read_coordinate(exif: &Exif, tag: Tag, ref_tag: Tag, negative: u8) -> Option<f64> {
+    match exif.get_field(tag, In::PRIMARY) {
+        Some(xres) => match xres.value {
+            Value::Rational(ref v) if !v.is_empty() => {
+                let degrees = dms_to_degrees(v);
+                match read_ref(exif, ref_tag) {
+                    Some(c) if c == negative => Some(-degrees),
+                    Some(_) => Some(degrees),
+                    None => {
+                        eprintln!("{} tag is missing", ref_tag);
+                        Some(degrees)
+                    }
+                }
+            }
+            _ => {
+                eprintln!("{} value is broken", tag);
+                None
+            }
+        },
+        None => {
+            eprintln!("{} tag is missing", tag);
+            None
+        }
+    }
+}
+
+/// 读取海拔，GPSAltitudeRef为1时表示海平面以下，缺失时返回None
+pub fn read_altitude(exif: &Exif) -> Option<f64> {
+    match exif.get_field(Tag::GPSAltitude, In::PRIMARY) {
+        Some(xres) => match xres.value {
+            Value::Rational(ref v) if !v.is_empty() => {
+                let below_sea_level = exif
+                    .get_field(Tag::GPSAltitudeRef, In::PRIMARY)
+                    .and_then(|f| f.value.get_uint(0))
+                    == Some(1);
+                let altitude = v[0].to_f64();
+                Some(if below_sea_level { -altitude } else { altitude })
+            }
+            _ => {
+                eprintln!("GPSAltitude value is broken");
+                None
+            }
+        },
+        None => None,
+    }
+}
+
+/// 解析exif中的GPS信息，返回WGS84的[经度,纬度,海拔]，缺失的值为0
+pub fn read_lonlat(exif: &Exif) -> Vec<f64> {
+    vec![
+        read_coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W').unwrap_or(0.0),
+        read_coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S').unwrap_or(0.0),
+        read_altitude(exif).unwrap_or(0.0),
+    ]
+}
+
+/// exif中的拍摄时间，time为按相机本地时间计算的Unix时间（秒），offset为OffsetTimeOriginal（分钟）
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct CaptureTime {
+    pub time: f64,
+    pub offset: Option<i32>,
+}
+
+impl CaptureTime {
+    /// 转为UTC的Unix时间（秒），timezone（分钟）为空时使用OffsetTimeOriginal，两者均无时按UTC处理
+    pub fn utc(&self, timezone: Option<i32>) -> f64 {
+        self.time - timezone.or(self.offset).unwrap_or(0) as f64 * 60.0
+    }
+}
+
+/// 读取拍摄时间DateTimeOriginal及其亚秒、时区
+pub fn read_capture_time(exif: &Exif) -> Option<CaptureTime> {
+    let ascii = |tag: Tag| match exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(ref v) if !v.is_empty() => Some(v[0].clone()),
+        _ => None,
+    };
+    let mut datetime = exif::DateTime::from_ascii(&ascii(Tag::DateTimeOriginal)?).ok()?;
+    if let Some(subsec) = ascii(Tag::SubSecTimeOriginal) {
+        let _ = datetime.parse_subsec(&subsec);
+    }
+    if let Some(offset) = ascii(Tag::OffsetTimeOriginal) {
+        let _ = datetime.parse_offset(&offset);
+    }
+    let second = datetime.second as f64 + datetime.nanosecond.unwrap_or(0) as f64 / 1e9;
+    Some(CaptureTime {
+        time: track::unix_seconds(
+            datetime.year as i64,
+            datetime.month as u32,
+            datetime.day as u32,
+            datetime.hour as u32,
+            datetime.minute as u32,
+            second,
+        ),
+        offset: datetime.offset.map(i32::from),
+    })
+}
+
+/// 是否包含GPS经纬度
+pub fn has_gps(exif: &Exif) -> bool {
+    exif.get_field(Tag::GPSLatitude, In::PRIMARY).is_some()
+        && exif.get_field(Tag::GPSLongitude, In::PRIMARY).is_some()
+}
+
+/// 磁北方向未指定磁偏角时的警告
+pub const MAGNETIC_WITHOUT_DECLINATION: &str =
+    "GPSImgDirection为磁北方向，未指定磁偏角，按真北处理";
+
+/// 读取GPSImgDirection，磁北方向按磁偏角（东偏为正）修正为真北，未提供磁偏角时按原值使用并返回警告
+fn read_img_direction(
+    exif: &Exif,
+    declination: Option<f64>,
+) -> Option<(f64, Option<&'static str>)> {
+    let direction = match exif.get_field(Tag::GPSImgDirection, In::PRIMARY)?.value {
+        Value::Rational(ref v) if !v.is_empty() => v[0].to_f64(),
+        _ => {
+            eprintln!("GPSImgDirection value is broken");
+            return None;
+        }
+    };
+    match (read_ref(exif, Tag::GPSImgDirectionRef), declination) {
+        (Some(b'M'), Some(declination)) => Some((direction + declination, None)),
+        (Some(b'M'), None) => Some((direction, Some(MAGNETIC_WITHOUT_DECLINATION))),
+        _ => Some((direction, None)),
+    }
+}
+
+/// 全景图中心的真北方位角(度，[0,360))，优先使用XMP的GPano:PoseHeadingDegrees，其次为GPSImgDirection，
+/// 同时返回读取朝向时的警告
+pub fn read_heading(
+    exif: Option<&Exif>,
+    xmp: Option<&str>,
+    declination: Option<f64>,
+) -> (Option<f64>, Option<&'static str>) {
+    let pose_heading = xmp.and_then(|xmp| xmp::xmp_f64(xmp, "GPano:PoseHeadingDegrees"));
+    let (heading, warning) = match pose_heading {
+        Some(heading) => (Some(heading), None),
+        None => match exif.and_then(|exif| read_img_direction(exif, declination)) {
+            Some((heading, warning)) => (Some(heading), warning),
+            None => (None, None),
+        },
+    };
+    (heading.map(|heading| heading.rem_euclid(360.0)), warning)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use exif::{Field, Rational};
+
+    fn dms(d: u32, m: u32, s: u32) -> Value {
+        Value::Rational(vec![
+            Rational { num: d, denom: 1 },
+            Rational { num: m, denom: 1 },
+            Rational { num: s, denom: 100 },
+        ])
+    }
+
+    fn ascii(s: &str) -> Value {
+        Value::Ascii(vec![s.as_bytes().to_vec()])
+    }
+
+    /// 构造只包含GPS信息的exif
+    fn fixture(lat_ref: &str, lon_ref: &str, altitude_ref: Option<u8>) -> Exif {
+        let mut fields = vec![
+            Field {
+                tag: Tag::GPSLatitude,
+                ifd_num: In::PRIMARY,
+                value: dms(30, 15, 1800),
+            },
+            Field {
+                tag: Tag::GPSLatitudeRef,
+                ifd_num: In::PRIMARY,
+                value: ascii(lat_ref),
+            },
+            Field {
+                tag: Tag::GPSLongitude,
+                ifd_num: In::PRIMARY,
+                value: dms(120, 30, 3600),
+            },
+            Field {
+                tag: Tag::GPSLongitudeRef,
+                ifd_num: In::PRIMARY,
+                value: ascii(lon_ref),
+            },
+            Field {
+                tag: Tag::GPSAltitude,
+                ifd_num: In::PRIMARY,
+                value: Value::Rational(vec![Rational { num: 125, denom: 2 }]),
+            },
+        ];
+        if let Some(altitude_ref) = altitude_ref {
+            fields.push(Field {
+                tag: Tag::GPSAltitudeRef,
+                ifd_num: In::PRIMARY,
+                value: Value::Byte(vec![altitude_ref]),
+            });
+        }
+        exif::Reader::new()
+            .read_raw(testutil::exif_data(&fields))
+            .unwrap()
+    }
+
+    /// 构造只包含GPSImgDirection的exif，方向以百分之一度为单位
+    fn direction_fixture(hundredths: u32, reference: &str) -> Exif {
+        let data = testutil::exif_data(&testutil::direction_fields(hundredths, reference));
+        exif::Reader::new().read_raw(data).unwrap()
+    }
+
+    fn assert_heading(heading: Option<f64>, expected: f64) {
+        let heading = heading.unwrap();
+        assert!(
+            (heading - expected).abs() < 1e-9,
+            "{} != {}",
+            heading,
+            expected
+        );
+    }
+
+    fn assert_lonlat(exif: &Exif, expected: [f64; 3]) {
+        let lonlat = read_lonlat(exif);
+        for (v, e) in lonlat.iter().zip(expected) {
+            assert!((v - e).abs() < 1e-9, "{:?} != {:?}", lonlat, expected);
+        }
+    }
+
+    #[test]
+    fn capture_time_timezone() {
+        let time = CaptureTime {
+            time: 1709324415.0,
+            offset: Some(480),
+        };
+        assert_eq!(time.utc(None), 1709295615.0);
+        //指定的时区优先于OffsetTimeOriginal
+        assert_eq!(time.utc(Some(0)), 1709324415.0);
+        assert_eq!(
+            CaptureTime {
+                offset: None,
+                ..time
+            }
+            .utc(None),
+            1709324415.0
+        );
+    }
+
+    #[test]
+    fn north_east() {
+        assert_lonlat(&fixture("N", "E", Some(0)), [120.51, 30.255, 62.5]);
+    }
+
+    #[test]
+    fn north_west() {
+        assert_lonlat(&fixture("N", "W", Some(0)), [-120.51, 30.255, 62.5]);
+    }
+
+    #[test]
+    fn south_east() {
+        assert_lonlat(&fixture("S", "E", None), [120.51, -30.255, 62.5]);
+    }
+
+    #[test]
+    fn south_west_below_sea_level() {
+        assert_lonlat(&fixture("s", "w", Some(1)), [-120.51, -30.255, -62.5]);
+    }
+
+    #[test]
+    fn xmp_heading_takes_priority() {
+        let exif = direction_fixture(4500, "T");
+        let xmp = r#"<rdf:Description GPano:PoseHeadingDegrees="120.5"/>"#;
+        assert_heading(read_heading(Some(&exif), Some(xmp), None).0, 120.5);
+        //XMP中没有朝向时使用GPSImgDirection
+        let xmp = r#"<rdf:Description GPano:PosePitchDegrees="1.0"/>"#;
+        assert_heading(read_heading(Some(&exif), Some(xmp), None).0, 45.0);
+        assert_eq!(read_heading(None, Some(xmp), None), (None, None));
+    }
+
+    #[test]
+    fn magnetic_heading_uses_declination() {
+        let exif = direction_fixture(10000, "M");
+        assert_heading(read_heading(Some(&exif), None, Some(-6.5)).0, 93.5);
+        //未指定磁偏角时按真北处理，并返回警告
+        let (heading, warning) = read_heading(Some(&exif), None, None);
+        assert_heading(heading, 100.0);
+        assert_eq!(warning, Some(MAGNETIC_WITHOUT_DECLINATION));
+        //XMP中有朝向时不使用GPSImgDirection，没有警告
+        let xmp = r#"<rdf:Description GPano:PoseHeadingDegrees="120.5"/>"#;
+        assert_eq!(read_heading(Some(&exif), Some(xmp), None).1, None);
+        //真北方向不受磁偏角影响
+        let exif = direction_fixture(10000, "T");
+        assert_heading(read_heading(Some(&exif), None, Some(-6.5)).0, 100.0);
+    }
+
+    #[test]
+    fn heading_wraps_into_range() {
+        let exif = direction_fixture(35000, "M");
+        assert_heading(read_heading(Some(&exif), None, Some(15.0)).0, 5.0);
+        let exif = direction_fixture(500, "M");
+        assert_heading(read_heading(Some(&exif), None, Some(-15.0)).0, 350.0);
+        let xmp = r#"<rdf:Description GPano:PoseHeadingDegrees="-90"/>"#;
+        assert_heading(read_heading(None, Some(xmp), None).0, 270.0);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::encode::EncodeOptions;
+use clap::ValueEnum;
+use image::imageops::{self, FilterType};
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// 默认缩略图的名称，对应PImage的thumbnail
+pub const THUMBNAIL_NAME: &str = "low";
+
+/// 缩放预览图使用的滤波器
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewFilter {
+    /// 最近邻，速度最快但有锯齿（旧版行为）
+    Nearest,
+    Triangle,
+    Catmullrom,
+    #[default]
+    Lanczos3,
+}
+
+impl PreviewFilter {
+    fn filter_type(&self) -> FilterType {
+        match self {
+            PreviewFilter::Nearest => FilterType::Nearest,
+            PreviewFilter::Triangle => FilterType::Triangle,
+            PreviewFilter::Catmullrom => FilterType::CatmullRom,
+            PreviewFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// 预览图尺寸，按宽度及一半的高度等比缩放到框内
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PreviewSize {
+    pub name: String,
+    pub width: u32,
+}
+
+impl PreviewSize {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty()
+            || !self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!("预览图名称{}只能包含字母、数字、-和_", self.name));
+        }
+        if self.width < 2 {
+            return Err(format!("预览图{}的宽度需大于1", self.name));
+        }
+        Ok(())
+    }
+}
+
+/// 解析命令行的name:width格式
+pub fn parse_preview(text: &str) -> Result<PreviewSize, String> {
+    let (name, width) = text
+        .split_once(':')
+        .ok_or_else(|| format!("预览图{}的格式应为名称:宽度", text))?;
+    let size = PreviewSize {
+        name: name.trim().to_string(),
+        width: width
+            .trim()
+            .parse()
+            .map_err(|_| format!("预览图{}的宽度无效", text))?,
+    };
+    size.validate()?;
+    Ok(size)
+}
+
+/// 缩略图加上额外的预览图，检查名称是否重复
+pub fn preview_sizes(extra: &[PreviewSize]) -> Result<Vec<PreviewSize>, String> {
+    let mut sizes = vec![PreviewSize {
+        name: THUMBNAIL_NAME.to_string(),
+        width: crate::THUMBNAIL_WIDTH,
+    }];
+    for size in extra {
+        size.validate()?;
+        if sizes.iter().any(|s| s.name == size.name) {
+            return Err(format!("预览图名称{}重复或为缩略图保留", size.name));
+        }
+        sizes.push(size.clone());
+    }
+    Ok(sizes)
+}
+
+/// 输出的预览图
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PPreview {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    /// 相对于输出根目录
+    pub path: String,
+}
+
+/// 等比缩放到宽width、高width/2的框内，不放大
+pub fn fit_size(width: u32, height: u32, box_width: u32) -> (u32, u32) {
+    let scale = f64::min(
+        box_width as f64 / width as f64,
+        (box_width / 2) as f64 / height as f64,
+    )
+    .min(1.0);
+    (
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+    )
+}
+
+/// 从已解码的图像输出各尺寸预览图，文件名为{stem}_{name}
+pub fn render_previews(
+    img: &RgbImage,
+    sizes: &[PreviewSize],
+    filter: PreviewFilter,
+    encode: &EncodeOptions,
+    folder: &Path,
+    relpath: &str,
+    stem: &str,
+) -> Result<Vec<PPreview>, Box<dyn std::error::Error>> {
+    let mut previews = Vec::new();
+    for size in sizes {
+        let (width, height) = fit_size(img.width(), img.height(), size.width);
+        //缩略图沿用旧版的大写扩展名
+        let name = if size.name == THUMBNAIL_NAME {
+            encode.with_extension(&format!("{}_{}.JPG", stem, size.name))
+        } else {
+            encode.with_extension(&format!("{}_{}.jpg", stem, size.name))
+        };
+        let outputf = folder.join(&name);
+        println!("导出预览图{:?}", outputf);
+        let resized = imageops::resize(img, width, height, filter.filter_type());
+        encode.save(&resized, &outputf)?;
+        previews.push(PPreview {
+            name: size.name.clone(),
+            width,
+            height,
+            path: format!("{}/{}", relpath, name),
+        });
+    }
+    Ok(previews)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_keeps_aspect_without_upscaling() {
+        assert_eq!(fit_size(8000, 4000, 1024), (1024, 512));
+        assert_eq!(fit_size(6000, 2000, 1024), (1024, 341));
+        assert_eq!(fit_size(3000, 2000, 1024), (768, 512));
+        assert_eq!(fit_size(600, 300, 2048), (600, 300));
+    }
+
+    #[test]
+    fn parse_name_and_width() {
+        assert_eq!(
+            parse_preview("icon:256"),
+            Ok(PreviewSize {
+                name: "icon".to_string(),
+                width: 256
+            })
+        );
+        assert!(parse_preview("icon").is_err());
+        assert!(parse_preview("a b:256").is_err());
+        assert!(preview_sizes(&[parse_preview("low:512").unwrap()]).is_err());
+    }
+}
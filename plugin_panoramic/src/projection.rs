@@ -0,0 +1,168 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// CGCS2000椭球，与WGS84长半轴相同，扁率略有差异
+const CGCS2000_A: f64 = 6378137.0;
+const CGCS2000_F: f64 = 1.0 / 298.257222101;
+const WGS84_A: f64 = 6378137.0;
+const WGS84_F: f64 = 1.0 / 298.257223563;
+
+/// 投影坐标类型
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Projection {
+    /// CGCS2000 3度带高斯-克吕格投影
+    Gk3,
+    /// CGCS2000 6度带高斯-克吕格投影
+    Gk6,
+    /// WGS84 UTM投影
+    Utm,
+}
+
+/// 投影坐标，东坐标不含带号，加500000米东偏
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PProjected {
+    pub easting: f64,
+    pub northing: f64,
+    pub zone: u32,
+    /// 对应的EPSG代码，高斯投影为不含带号的中央经线版本
+    pub epsg: u32,
+}
+
+impl Projection {
+    /// 根据经度自动选择带号
+    pub fn zone_of(&self, lon: f64) -> u32 {
+        match self {
+            Projection::Gk3 => (lon / 3.0).round().max(1.0) as u32,
+            Projection::Gk6 => ((lon / 6.0).floor() + 1.0).max(1.0) as u32,
+            Projection::Utm => (((lon + 180.0) / 6.0).floor() as u32).clamp(0, 59) + 1,
+        }
+    }
+
+    pub fn central_meridian(&self, zone: u32) -> f64 {
+        match self {
+            Projection::Gk3 => 3.0 * zone as f64,
+            Projection::Gk6 => 6.0 * zone as f64 - 3.0,
+            Projection::Utm => 6.0 * zone as f64 - 183.0,
+        }
+    }
+
+    /// 国内范围外的高斯投影带没有对应的EPSG代码，返回0
+    pub fn epsg(&self, zone: u32, south: bool) -> u32 {
+        match self {
+            Projection::Gk3 if (25..=45).contains(&zone) => 4534 + zone - 25,
+            Projection::Gk6 if (13..=23).contains(&zone) => 4502 + zone - 13,
+            Projection::Gk3 | Projection::Gk6 => 0,
+            Projection::Utm if south => 32700 + zone,
+            Projection::Utm => 32600 + zone,
+        }
+    }
+
+    /// 经纬度投影到指定带号，zone为None时按经度自动选择
+    pub fn project(&self, lon: f64, lat: f64, zone: Option<u32>) -> PProjected {
+        let zone = zone.unwrap_or_else(|| self.zone_of(lon));
+        let (a, f, k0) = match self {
+            Projection::Gk3 | Projection::Gk6 => (CGCS2000_A, CGCS2000_F, 1.0),
+            Projection::Utm => (WGS84_A, WGS84_F, 0.9996),
+        };
+        let (easting, northing) =
+            transverse_mercator(lon, lat, self.central_meridian(zone), a, f, k0);
+        let south = *self == Projection::Utm && lat < 0.0;
+        PProjected {
+            easting: easting + 500000.0,
+            northing: if south {
+                northing + 10000000.0
+            } else {
+                northing
+            },
+            zone,
+            epsg: self.epsg(zone, south),
+        }
+    }
+}
+
+/// 横轴墨卡托投影，采用Krüger级数展开至n的4次方，返回相对中央经线与赤道的(东,北)坐标
+pub fn transverse_mercator(lon: f64, lat: f64, lon0: f64, a: f64, f: f64, k0: f64) -> (f64, f64) {
+    let n = f / (2.0 - f);
+    let (n2, n3, n4) = (n * n, n * n * n, n * n * n * n);
+    let big_a = a / (1.0 + n) * (1.0 + n2 / 4.0 + n4 / 64.0);
+    let alpha = [
+        n / 2.0 - 2.0 * n2 / 3.0 + 5.0 * n3 / 16.0 + 41.0 * n4 / 180.0,
+        13.0 * n2 / 48.0 - 3.0 * n3 / 5.0 + 557.0 * n4 / 1440.0,
+        61.0 * n3 / 240.0 - 103.0 * n4 / 140.0,
+        49561.0 * n4 / 161280.0,
+    ];
+    let phi = lat.to_radians();
+    let dlambda = (lon - lon0).to_radians();
+    let e2n = 2.0 * n.sqrt() / (1.0 + n);
+    let t = (phi.sin().atanh() - e2n * (e2n * phi.sin()).atanh()).sinh();
+    let xi = t.atan2(dlambda.cos());
+    let eta = (dlambda.sin() / (1.0 + t * t).sqrt()).atanh();
+    let mut easting = eta;
+    let mut northing = xi;
+    for (j, alpha) in alpha.iter().enumerate() {
+        let k = 2.0 * (j + 1) as f64;
+        easting += alpha * (k * xi).cos() * (k * eta).sinh();
+        northing += alpha * (k * xi).sin() * (k * eta).cosh();
+    }
+    (k0 * big_a * easting, k0 * big_a * northing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //天安门
+    const LON: f64 = 116.391248;
+    const LAT: f64 = 39.907374;
+
+    #[test]
+    fn projects_known_point() {
+        let utm = Projection::Utm.project(LON, LAT, None);
+        assert_eq!((utm.zone, utm.epsg), (50, 32650));
+        assert!((utm.easting - 447966.79).abs() < 0.01);
+        assert!((utm.northing - 4417654.06).abs() < 0.01);
+
+        let gk3 = Projection::Gk3.project(LON, LAT, None);
+        assert_eq!((gk3.zone, gk3.epsg), (39, 4548));
+        assert!((gk3.easting - 447945.97).abs() < 0.01);
+    }
+
+    #[test]
+    fn zones_and_central_meridians() {
+        assert_eq!(Projection::Gk3.zone_of(LON), 39);
+        assert_eq!(Projection::Gk3.zone_of(118.6), 40);
+        assert_eq!(Projection::Gk6.zone_of(LON), 20);
+        assert_eq!(Projection::Utm.zone_of(LON), 50);
+        assert_eq!(Projection::Utm.zone_of(-180.0), 1);
+        assert_eq!(Projection::Utm.zone_of(180.0), 60);
+        assert_eq!(Projection::Gk3.central_meridian(39), 117.0);
+        assert_eq!(Projection::Gk6.central_meridian(20), 117.0);
+        assert_eq!(Projection::Utm.central_meridian(50), 117.0);
+        //中央经线上东坐标为500000
+        assert!((Projection::Gk6.project(117.0, LAT, None).easting - 500000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn epsg_codes() {
+        assert_eq!(Projection::Gk3.epsg(25, false), 4534);
+        assert_eq!(Projection::Gk3.epsg(45, false), 4554);
+        assert_eq!(Projection::Gk3.epsg(46, false), 0);
+        assert_eq!(Projection::Gk6.epsg(13, false), 4502);
+        assert_eq!(Projection::Gk6.epsg(23, false), 4512);
+        assert_eq!(Projection::Gk6.epsg(12, false), 0);
+        assert_eq!(Projection::Utm.epsg(50, false), 32650);
+        assert_eq!(Projection::Utm.epsg(56, true), 32756);
+    }
+
+    #[test]
+    fn southern_hemisphere_false_northing() {
+        let north = Projection::Utm.project(151.2093, 33.8688, None);
+        let south = Projection::Utm.project(151.2093, -33.8688, None);
+        assert_eq!((south.zone, south.epsg), (56, 32756));
+        assert!((south.easting - north.easting).abs() < 1e-6);
+        assert!((south.northing - (10000000.0 - north.northing)).abs() < 1e-6);
+        //高斯投影不加南半球北偏
+        assert!(Projection::Gk6.project(117.0, -1.0, None).northing < 0.0);
+    }
+}
@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+pub const REPORT_NAME: &str = "qreport.json";
+
+/// 单个源文件的问题
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PIssue {
+    /// 源文件相对于输入目录的路径
+    pub source: String,
+    pub reason: String,
+}
+
+/// 编译报告
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct BuildReport {
+    /// 新编译成功的全景图数量
+    pub built: usize,
+    /// 未变化而跳过的全景图数量
+    pub skipped: usize,
+    /// 编译失败的全景图，不写入索引
+    pub failures: Vec<PIssue>,
+    /// 已编译但存在问题的全景图，如缺少exif、标注格式错误
+    pub warnings: Vec<PIssue>,
+}
+
+/// 多线程编译时收集报告
+#[derive(Default)]
+pub struct Reporter {
+    report: Mutex<BuildReport>,
+}
+
+impl Reporter {
+    pub fn built(&self) {
+        self.report.lock().unwrap().built += 1;
+    }
+
+    pub fn skipped(&self) {
+        self.report.lock().unwrap().skipped += 1;
+    }
+
+    pub fn fail(&self, source: &str, reason: impl ToString) {
+        let reason = reason.to_string();
+        eprintln!("全景图{}编译失败：{}", source, reason);
+        self.report.lock().unwrap().failures.push(PIssue {
+            source: source.to_string(),
+            reason,
+        });
+    }
+
+    pub fn warn(&self, source: &str, reason: impl ToString) {
+        let reason = reason.to_string();
+        eprintln!("全景图{}：{}", source, reason);
+        self.report.lock().unwrap().warnings.push(PIssue {
+            source: source.to_string(),
+            reason,
+        });
+    }
+
+    pub fn finish(self) -> BuildReport {
+        let mut report = self.report.into_inner().unwrap();
+        report.failures.sort_by(|a, b| a.source.cmp(&b.source));
+        report.warnings.sort_by(|a, b| a.source.cmp(&b.source));
+        report
+    }
+}
+
+impl BuildReport {
+    pub fn save(&self, output: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(
+            output.join(REPORT_NAME),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    /// 控制台输出汇总
+    pub fn print_summary(&self) {
+        println!(
+            "编译{}张，跳过{}张，失败{}张，警告{}条",
+            self.built,
+            self.skipped,
+            self.failures.len(),
+            self.warnings.len()
+        );
+        for issue in &self.failures {
+            println!("  失败 {}：{}", issue.source, issue.reason);
+        }
+    }
+
+    pub fn has_failures(&self) -> bool {
+        !self.failures.is_empty()
+    }
+}
@@ -0,0 +1,341 @@
+use crate::annotation;
+use crate::cubemap::sample_bilinear;
+use crate::encode::{EncodeOptions, OutputFormat};
+use crate::source::{self, ToneMap};
+use crate::sphere;
+use crate::tile::PaddedImage;
+use clap::Args;
+use image::RgbImage;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 透视视图的朝向、视场角及输出尺寸，角度单位为度
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SnapshotView {
+    /// 以全景图中心为0，向右为正
+    pub yaw: f64,
+    /// 向上为正
+    pub pitch: f64,
+    /// 水平视场角
+    pub fov: f64,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl SnapshotView {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.fov > 0.0 && self.fov < 180.0) {
+            return Err(format!("视场角{}需在0至180度之间", self.fov));
+        }
+        if !(-90.0..=90.0).contains(&self.pitch) {
+            return Err(format!("俯仰角{}超出[-90,90]", self.pitch));
+        }
+        if !self.yaw.is_finite() {
+            return Err(format!("方位角{}不是有效数字", self.yaw));
+        }
+        if self.width == 0 || self.height == 0 {
+            return Err("快照尺寸不能为0".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// 从等距圆柱投影全景图渲染透视（直线）投影的快照
+pub fn render_snapshot(img: &PaddedImage, view: &SnapshotView) -> RgbImage {
+    let (width, height) = (img.width(), img.height());
+    let half_width = (view.fov.to_radians() / 2.0).tan();
+    let half_height = half_width * view.height as f64 / view.width as f64;
+    let (sin_yaw, cos_yaw) = view.yaw.to_radians().sin_cos();
+    let (sin_pitch, cos_pitch) = view.pitch.to_radians().sin_cos();
+    let mut buf = vec![0u8; (view.width * view.height * 3) as usize];
+    buf.par_chunks_mut((view.width * 3) as usize)
+        .enumerate()
+        .for_each(|(j, row)| {
+            let y = (1.0 - 2.0 * (j as f64 + 0.5) / view.height as f64) * half_height;
+            for i in 0..view.width as usize {
+                let x = (2.0 * (i as f64 + 0.5) / view.width as f64 - 1.0) * half_width;
+                //先绕x轴俯仰，再绕y轴转向，坐标系与立方体贴图一致
+                let (y, z) = (y * cos_pitch + sin_pitch, cos_pitch - y * sin_pitch);
+                let (x, z) = (x * cos_yaw + z * sin_yaw, z * cos_yaw - x * sin_yaw);
+                let (yaw, pitch) = sphere::vector_to_angles(x, y, z);
+                let (u, v) = sphere::angles_to_canvas(yaw, pitch, width, height);
+                let pixel = sample_bilinear(img, u - 0.5, v - 0.5);
+                row[i * 3..i * 3 + 3].copy_from_slice(&pixel.0);
+            }
+        });
+    RgbImage::from_raw(view.width, view.height, buf).unwrap()
+}
+
+/// snapshot子命令的参数
+#[derive(Args, Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SnapshotArgs {
+    /// 全景图源文件
+    #[arg(short, long)]
+    pub image: PathBuf,
+    /// 输出文件，指定--markers时为输出目录
+    #[arg(short, long)]
+    pub output: PathBuf,
+    /// 方位角（度），以全景图中心为0，向右为正
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub yaw: f64,
+    /// 俯仰角（度），向上为正
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub pitch: f64,
+    /// 水平视场角（度）
+    #[arg(long, default_value_t = 90.0)]
+    pub fov: f64,
+    /// 快照宽度
+    #[arg(long, default_value_t = 1024)]
+    pub width: u32,
+    /// 快照高度
+    #[arg(long, default_value_t = 768)]
+    pub height: u32,
+    /// 为同名标注文件中的每个标注点输出一张快照，朝向为标注点的方位角及俯仰角
+    #[arg(long)]
+    pub markers: bool,
+    /// 输出格式
+    #[arg(long, value_enum, default_value_t = OutputFormat::Jpeg)]
+    pub format: OutputFormat,
+    /// 输出质量，1-100
+    #[arg(long)]
+    pub quality: Option<u8>,
+    /// 16位及浮点源文件转为8位的方式
+    #[arg(long, value_enum, default_value_t = ToneMap::Scale)]
+    pub tonemap: ToneMap,
+}
+
+impl Default for SnapshotArgs {
+    fn default() -> Self {
+        SnapshotArgs {
+            image: PathBuf::new(),
+            output: PathBuf::new(),
+            yaw: 0.0,
+            pitch: 0.0,
+            fov: 90.0,
+            width: 1024,
+            height: 768,
+            markers: false,
+            format: OutputFormat::Jpeg,
+            quality: None,
+            tonemap: ToneMap::Scale,
+        }
+    }
+}
+
+/// 输出的快照
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PSnapshot {
+    /// 标注点快照对应的标注点id
+    pub marker: Option<String>,
+    pub view: SnapshotView,
+    pub path: PathBuf,
+}
+
+/// 渲染单个视图或全部标注点的快照，返回输出的文件
+pub fn run_snapshot(args: &SnapshotArgs) -> Result<Vec<PSnapshot>, Box<dyn std::error::Error>> {
+    let encode = EncodeOptions {
+        format: args.format,
+        quality: args.quality.unwrap_or(args.format.default_quality()),
+        progressive: false,
+    };
+    encode.validate()?;
+    let view = SnapshotView {
+        yaw: args.yaw,
+        pitch: args.pitch,
+        fov: args.fov,
+        width: args.width,
+        height: args.height,
+    };
+    view.validate()?;
+    //单个视图按-o的文件名输出，扩展名需与输出格式一致
+    if !args.markers {
+        let ext = args
+            .output
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy();
+        if !args.format.matches_extension(&ext) {
+            return Err(format!(
+                "快照文件{}的扩展名与输出格式{}不一致",
+                args.output.display(),
+                args.format.extension()
+            )
+            .into());
+        }
+    }
+    let source = source::load_source(&args.image, args.tonemap)?;
+    let img = PaddedImage::new(&source.img, &source.panodata);
+    if !args.markers {
+        if let Some(parent) = args.output.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        save_snapshot(&img, &view, &encode, &args.output)?;
+        return Ok(vec![PSnapshot {
+            marker: None,
+            view,
+            path: args.output.clone(),
+        }]);
+    }
+
+    let annotation_path = annotation::find_annotation(&args.image)
+        .ok_or_else(|| format!("全景图{}没有标注文件", args.image.display()))?;
+    let text = fs::read_to_string(&annotation_path)?;
+    let imagename = args.image.file_stem().unwrap_or_default().to_string_lossy();
+    let (markers, errors) =
+        annotation::parse_annotations(&annotation_path, &text, &imagename, &source.panodata);
+    for error in errors {
+        println!("标注解析错误 {}", error);
+    }
+    fs::create_dir_all(&args.output)?;
+    let mut snapshots = Vec::new();
+    for marker in markers {
+        let view = SnapshotView {
+            yaw: marker.yaw.to_degrees(),
+            pitch: marker.pitch.to_degrees(),
+            ..view
+        };
+        //id中可能含有点号，直接追加扩展名
+        let path = args
+            .output
+            .join(format!("{}.{}", marker.id, encode.format.extension()));
+        save_snapshot(&img, &view, &encode, &path)?;
+        snapshots.push(PSnapshot {
+            marker: Some(marker.id),
+            view,
+            path,
+        });
+    }
+    Ok(snapshots)
+}
+
+fn save_snapshot(
+    img: &PaddedImage,
+    view: &SnapshotView,
+    encode: &EncodeOptions,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("导出快照{:?}", path);
+    encode.save(&render_snapshot(img, view), path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use crate::tile::PPanoData;
+    use image::Rgb;
+
+    //红色通道为经度，绿色通道为纬度
+    fn panorama() -> RgbImage {
+        RgbImage::from_fn(360, 180, |x, y| {
+            Rgb([(x * 255 / 359) as u8, (y * 255 / 179) as u8, 0])
+        })
+    }
+
+    fn view(yaw: f64, pitch: f64) -> SnapshotView {
+        SnapshotView {
+            yaw,
+            pitch,
+            fov: 90.0,
+            width: 64,
+            height: 48,
+        }
+    }
+
+    #[test]
+    fn center_follows_yaw_and_pitch() {
+        let img = panorama();
+        let padded = PaddedImage::new(&img, &PPanoData::centered(360, 180));
+        let front = render_snapshot(&padded, &view(0.0, 0.0));
+        let right = render_snapshot(&padded, &view(90.0, 0.0));
+        let up = render_snapshot(&padded, &view(0.0, 45.0));
+        let center = |snapshot: &RgbImage| *snapshot.get_pixel(32, 24);
+        assert!(center(&front).0[0].abs_diff(img.get_pixel(180, 90).0[0]) <= 1);
+        assert!(center(&right).0[0].abs_diff(img.get_pixel(270, 90).0[0]) <= 1);
+        assert!(center(&up).0[1].abs_diff(img.get_pixel(180, 45).0[1]) <= 1);
+    }
+
+    #[test]
+    fn edges_match_field_of_view() {
+        let img = panorama();
+        let padded = PaddedImage::new(&img, &PPanoData::centered(360, 180));
+        let snapshot = render_snapshot(&padded, &view(0.0, 0.0));
+        //左右边缘约为±45度
+        assert!(snapshot.get_pixel(0, 24).0[0].abs_diff(img.get_pixel(135, 90).0[0]) <= 2);
+        assert!(snapshot.get_pixel(63, 24).0[0].abs_diff(img.get_pixel(225, 90).0[0]) <= 2);
+        assert!(view(0.0, 0.0).validate().is_ok());
+        assert!(SnapshotView {
+            fov: 180.0,
+            ..view(0.0, 0.0)
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn writes_one_snapshot_per_marker() {
+        let folder = testutil::temp_folder("snapshot-markers");
+        let img = panorama();
+        let image = folder.join("p.v2.png");
+        img.save(&image).unwrap();
+        fs::write(
+            folder.join("p.v2.txt"),
+            "angle,90,0,pole,杆\npixel,180,45,sign,牌\nangle,10\n",
+        )
+        .unwrap();
+        let args = SnapshotArgs {
+            image: image.clone(),
+            output: folder.join("markers"),
+            width: 64,
+            height: 48,
+            markers: true,
+            format: OutputFormat::Png,
+            ..Default::default()
+        };
+        let snapshots = run_snapshot(&args).unwrap();
+        let ids: Vec<&str> = snapshots
+            .iter()
+            .map(|s| s.marker.as_deref().unwrap())
+            .collect();
+        assert_eq!(ids, ["p.v2-1", "p.v2-2"]);
+        for (snapshot, name) in snapshots.iter().zip(["p.v2-1.png", "p.v2-2.png"]) {
+            assert_eq!(snapshot.path, folder.join("markers").join(name));
+            let written = image::open(&snapshot.path).unwrap().into_rgb8();
+            assert_eq!(written.dimensions(), (64, 48));
+        }
+        //快照中心为标注点
+        assert!((snapshots[0].view.yaw - 90.0).abs() < 1e-9);
+        assert!((snapshots[1].view.pitch - 45.0).abs() < 1e-9);
+        let center = |i: usize| {
+            *image::open(&snapshots[i].path)
+                .unwrap()
+                .into_rgb8()
+                .get_pixel(32, 24)
+        };
+        assert!(center(0).0[0].abs_diff(img.get_pixel(270, 90).0[0]) <= 1);
+        assert!(center(1).0[1].abs_diff(img.get_pixel(180, 45).0[1]) <= 1);
+
+        //不指定--markers时输出单个视图，自动创建上级目录
+        let single = SnapshotArgs {
+            output: folder.join("views/front.png"),
+            markers: false,
+            ..args
+        };
+        let snapshots = run_snapshot(&single).unwrap();
+        assert_eq!(snapshots.len(), 1);
+        assert!(snapshots[0].marker.is_none());
+        let written = image::open(folder.join("views/front.png")).unwrap();
+        assert_eq!((written.width(), written.height()), (64, 48));
+        //扩展名与输出格式不一致时不输出
+        let mismatched = SnapshotArgs {
+            output: folder.join("views/side.jpg"),
+            ..single
+        };
+        assert!(run_snapshot(&mismatched).is_err());
+        assert!(!folder.join("views/side.jpg").exists());
+        let _ = fs::remove_dir_all(&folder);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::manifest::{self, Manifest, SourceStamp};
+use crate::tile::PPanoData;
+use crate::xmp;
+use clap::ValueEnum;
+use glob::{glob_with, MatchOptions, Pattern};
+use image::{DynamicImage, ImageBuffer, Rgb, RgbImage};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 支持的全景图源文件扩展名，不区分大小写
+pub const SOURCE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "tif", "tiff"];
+
+/// 高位深源文件转为8位的方式
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ToneMap {
+    /// 按位深线性缩放
+    #[default]
+    Scale,
+    /// 将0.1%至99.9%分位之间的亮度拉伸到8位，适合只使用了部分动态范围的16位及浮点图像
+    Stretch,
+}
+
+//拉伸时两端舍弃的像素比例
+const STRETCH_CLIP: f64 = 0.001;
+
+/// 匹配文件夹中指定扩展名文件的glob模式，文件夹名中的通配符按字面匹配
+pub fn extension_pattern(folder: &Path, ext: &str) -> String {
+    Path::new(&Pattern::escape(&folder.to_string_lossy()))
+        .join(format!("*.{}", ext))
+        .to_string_lossy()
+        .to_string()
+}
+
+/// 列出文件夹中的全景图源文件，按文件名排序
+pub fn list_sources(folder: &Path) -> Vec<PathBuf> {
+    let options = MatchOptions {
+        case_sensitive: false,
+        ..Default::default()
+    };
+    let mut files: Vec<PathBuf> = SOURCE_EXTENSIONS
+        .iter()
+        .filter_map(|ext| glob_with(&extension_pattern(folder, ext), options).ok())
+        .flat_map(|paths| paths.filter_map(|x| x.ok()))
+        .collect();
+    files.sort();
+    files
+}
+
+/// 图片名相同（如dup.jpg与dup.png）的源文件输出目录及id相同，只保留排序在前的一个；
+/// 与同级子分组文件夹同名的源文件输出目录与子分组相同，全部视为冲突。
+/// 返回(保留的,冲突的)；图片名不区分大小写比较
+pub fn split_stem_collisions(
+    files: Vec<PathBuf>,
+    folders: &[PathBuf],
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let mut stems: HashSet<String> = folders.iter().map(|folder| folder_key(folder)).collect();
+    files
+        .into_iter()
+        .partition(|path| stems.insert(stem_key(path)))
+}
+
+/// 比较图片名及输出目录名时使用的键，不区分大小写
+pub fn stem_key(path: &Path) -> String {
+    path.file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase()
+}
+
+/// 与stem_key比较的文件夹名，不区分大小写
+pub fn folder_key(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase()
+}
+
+/// 输入目录中属于编译输出的文件夹，遍历分组时跳过，使输出目录可以与输入目录相同
+pub struct OutputFolders {
+    /// 输出根目录
+    root: Option<PathBuf>,
+    /// 编译清单中记录的全景图输出目录
+    outputs: HashSet<PathBuf>,
+}
+
+impl OutputFolders {
+    pub fn new(output: &Path, manifest: &Manifest) -> OutputFolders {
+        OutputFolders {
+            root: canonical(output),
+            outputs: manifest
+                .entries
+                .values()
+                .flat_map(|entry| entry.outputs.iter())
+                .filter_map(|folder| canonical(&output.join(folder)))
+                .collect(),
+        }
+    }
+
+    fn contains(&self, folder: &Path) -> bool {
+        match canonical(folder) {
+            Some(folder) => self.root.as_ref() == Some(&folder) || self.outputs.contains(&folder),
+            None => false,
+        }
+    }
+}
+
+//空路径为当前目录
+fn canonical(path: &Path) -> Option<PathBuf> {
+    let path = if path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        path
+    };
+    fs::canonicalize(path).ok()
+}
+
+/// 文件夹中作为子分组的子文件夹，按名称排序，跳过输出根目录及编译清单中记录的输出目录
+pub fn group_folders(folder: &Path, outputs: &OutputFolders) -> std::io::Result<Vec<PathBuf>> {
+    Ok(crate::subfolders(folder)?
+        .into_iter()
+        .filter(|child| !outputs.contains(child))
+        .collect())
+}
+
+/// 解码一次的全景图及其元数据
+pub struct SourceImage {
+    pub img: RgbImage,
+    /// 原图在2:1画布中的位置，来自GPano或居中补齐
+    pub panodata: PPanoData,
+    pub exif: Result<exif::Exif, exif::Error>,
+    pub xmp: Option<String>,
+    /// 源文件指纹，由同一份文件数据计算
+    pub stamp: SourceStamp,
+}
+
+/// 读取并解码全景图，exif、XMP、指纹与图像共用同一份文件数据
+pub fn load_source(
+    path: &Path,
+    tonemap: ToneMap,
+) -> Result<SourceImage, Box<dyn std::error::Error>> {
+    let data = fs::read(path)?;
+    let stamp = manifest::stamp_data(path, &data)?;
+    let xmp = xmp::read_xmp(&data);
+    let exif = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(&data));
+    let img = to_rgb8(image::load_from_memory(&data)?, tonemap);
+    //解码后不再需要原始文件数据
+    drop(data);
+    let (width, height) = img.dimensions();
+    let panodata = match xmp.as_deref().and_then(xmp::read_gpano) {
+        Some(gpano) => PPanoData::from_gpano(&gpano, width, height).unwrap_or_else(|| {
+            println!("全景图{}的GPano裁切信息无效，按居中补齐", path.display());
+            PPanoData::centered(width, height)
+        }),
+        None => PPanoData::centered(width, height),
+    };
+    Ok(SourceImage {
+        img,
+        panodata,
+        exif,
+        xmp,
+        stamp,
+    })
+}
+
+/// 转为8位RGB，8位源文件直接转换，高位深源文件按指定方式映射
+pub fn to_rgb8(img: DynamicImage, tonemap: ToneMap) -> RgbImage {
+    let color = img.color();
+    let high_depth = color.bytes_per_pixel() > color.channel_count();
+    match img {
+        DynamicImage::ImageRgb8(img) => img,
+        img if high_depth && tonemap == ToneMap::Stretch => stretch(&img.into_rgb16()),
+        img => img.into_rgb8(),
+    }
+}
+
+//各通道使用同一映射，不改变色相
+fn stretch(img: &ImageBuffer<Rgb<u16>, Vec<u16>>) -> RgbImage {
+    let mut histogram = vec![0u64; 65536];
+    for value in img.as_raw() {
+        histogram[*value as usize] += 1;
+    }
+    let total = img.as_raw().len() as f64;
+    let percentile = |ratio: f64| -> usize {
+        let target = (total * ratio) as u64;
+        let mut sum = 0;
+        for (value, count) in histogram.iter().enumerate() {
+            sum += count;
+            if sum > target {
+                return value;
+            }
+        }
+        histogram.len() - 1
+    };
+    let low = percentile(STRETCH_CLIP) as f64;
+    let high = percentile(1.0 - STRETCH_CLIP) as f64;
+    let range = if high > low { high - low } else { 65535.0 };
+    let low = if high > low { low } else { 0.0 };
+    ImageBuffer::from_fn(img.width(), img.height(), |x, y| {
+        let pixel = img.get_pixel(x, y);
+        Rgb(pixel
+            .0
+            .map(|v| ((v as f64 - low) / range * 255.0).round().clamp(0.0, 255.0) as u8))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    fn gradient() -> DynamicImage {
+        //只使用了10000至30000的16位渐变
+        DynamicImage::ImageRgb16(ImageBuffer::from_fn(1000, 1, |x, _| {
+            let v = (10000 + x * 20) as u16;
+            Rgb([v, v, v])
+        }))
+    }
+
+    #[test]
+    fn list_sources_escapes_folder_name() {
+        let folder = testutil::temp_folder("source-[glob]");
+        for name in ["b.JPG", "a.png", "c.txt"] {
+            RgbImage::new(4, 2)
+                .save_with_format(folder.join(name), image::ImageFormat::Png)
+                .unwrap();
+        }
+        let files = list_sources(&folder);
+        let _ = fs::remove_dir_all(&folder);
+        assert_eq!(files, [folder.join("a.png"), folder.join("b.JPG")]);
+    }
+
+    #[test]
+    fn stem_key_ignores_case_and_extension() {
+        assert_eq!(stem_key(Path::new("G/A.png")), "a");
+        assert_eq!(stem_key(Path::new("G/a.PNG")), "a");
+        assert_eq!(folder_key(Path::new("G/P1")), stem_key(Path::new("p1.jpg")));
+        assert_ne!(stem_key(Path::new("G/a.b.png")), "a");
+    }
+
+    #[test]
+    fn stem_collisions_keep_first_source() {
+        let files = ["a/dup.jpg", "a/dup.png", "a/other.jpg", "a/DUP.tif"]
+            .map(PathBuf::from)
+            .to_vec();
+        let (kept, collided) = split_stem_collisions(files, &[PathBuf::from("a/Other")]);
+        assert_eq!(kept, [PathBuf::from("a/dup.jpg")]);
+        assert_eq!(
+            collided,
+            [
+                PathBuf::from("a/dup.png"),
+                PathBuf::from("a/other.jpg"),
+                PathBuf::from("a/DUP.tif")
+            ]
+        );
+    }
+
+    #[test]
+    fn scale_keeps_bit_depth_ratio() {
+        let img = to_rgb8(gradient(), ToneMap::Scale);
+        assert_eq!(img.get_pixel(0, 0).0[0], 39);
+        assert_eq!(img.get_pixel(999, 0).0[0], 117);
+    }
+
+    #[test]
+    fn stretch_uses_full_range() {
+        let img = to_rgb8(gradient(), ToneMap::Stretch);
+        assert_eq!(img.get_pixel(0, 0).0[0], 0);
+        assert_eq!(img.get_pixel(999, 0).0[0], 255);
+        assert!((120..136).contains(&img.get_pixel(500, 0).0[0]));
+    }
+
+    #[test]
+    fn stretch_leaves_8bit_unchanged() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(2, 2, Rgb([10, 20, 30])));
+        assert_eq!(
+            to_rgb8(img, ToneMap::Stretch).get_pixel(1, 1).0,
+            [10, 20, 30]
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//全景图像素、yaw/pitch与真北方位角之间的换算。
+//yaw、pitch为弧度，yaw以全景图中心为0向右为正，pitch向上为正；方位角为度，以真北为0顺时针为正。
+//像素坐标为连续坐标，像素(i,j)覆盖[i,i+1)x[j,j+1)，中心为(i+0.5,j+0.5)
+use crate::tile::PPanoData;
+use std::f64::consts::PI;
+
+/// 将yaw规整到[-π,π)
+pub fn normalize_yaw(yaw: f64) -> f64 {
+    (yaw + PI).rem_euclid(2.0 * PI) - PI
+}
+
+/// 完整2:1画布上的像素坐标转为yaw、pitch
+pub fn canvas_to_angles(x: f64, y: f64, width: u32, height: u32) -> (f64, f64) {
+    (
+        (x / width as f64 - 0.5) * 2.0 * PI,
+        (0.5 - y / height as f64) * PI,
+    )
+}
+
+/// yaw、pitch转为完整2:1画布上的像素坐标，x在[0,width)内循环
+pub fn angles_to_canvas(yaw: f64, pitch: f64, width: u32, height: u32) -> (f64, f64) {
+    (
+        (normalize_yaw(yaw) / (2.0 * PI) + 0.5) * width as f64,
+        (0.5 - pitch / PI) * height as f64,
+    )
+}
+
+/// 原图（补齐前）的像素坐标转为yaw、pitch
+pub fn pixel_to_angles(x: f64, y: f64, panodata: &PPanoData) -> (f64, f64) {
+    canvas_to_angles(
+        x + panodata.cropped_x as f64,
+        y + panodata.cropped_y as f64,
+        panodata.full_width,
+        panodata.full_height,
+    )
+}
+
+/// yaw、pitch转为原图（补齐前）的像素坐标，位于补齐区域时坐标超出原图范围
+pub fn angles_to_pixel(yaw: f64, pitch: f64, panodata: &PPanoData) -> (f64, f64) {
+    let (x, y) = angles_to_canvas(yaw, pitch, panodata.full_width, panodata.full_height);
+    (x - panodata.cropped_x as f64, y - panodata.cropped_y as f64)
+}
+
+/// 视线方向向量转为yaw、pitch，坐标系以全景图中心为前方(+z)，右方为+x，上方为+y
+pub fn vector_to_angles(x: f64, y: f64, z: f64) -> (f64, f64) {
+    (x.atan2(z), y.atan2((x * x + z * z).sqrt()))
+}
+
+/// yaw转为真北方位角，longitudeoffset为全景图中心的真北方位角（弧度）
+pub fn yaw_to_bearing(yaw: f64, longitudeoffset: f64) -> f64 {
+    (yaw + longitudeoffset).to_degrees().rem_euclid(360.0)
+}
+
+/// 真北方位角转为yaw，结果在[-π,π)内
+pub fn bearing_to_yaw(bearing: f64, longitudeoffset: f64) -> f64 {
+    normalize_yaw(bearing.to_radians() - longitudeoffset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn canvas_center_and_edges() {
+        assert_eq!(canvas_to_angles(400.0, 200.0, 800, 400), (0.0, 0.0));
+        assert_eq!(canvas_to_angles(0.0, 0.0, 800, 400), (-PI, PI / 2.0));
+        assert_eq!(angles_to_canvas(PI / 2.0, 0.0, 800, 400), (600.0, 200.0));
+        //yaw超出[-π,π)时循环
+        let (x, _) = angles_to_canvas(3.0 * PI / 2.0, 0.0, 800, 400);
+        assert!(close(x, 200.0));
+    }
+
+    #[test]
+    fn pixel_uses_crop_offset() {
+        let panodata = PPanoData::centered(800, 300);
+        //居中补齐后原图上方50像素为补齐区域
+        assert!(close(pixel_to_angles(400.0, 150.0, &panodata).1, 0.0));
+        let (yaw, pitch) = pixel_to_angles(123.5, 45.25, &panodata);
+        let (x, y) = angles_to_canvas(yaw, pitch, 800, 400);
+        assert!(close(x, 123.5) && close(y, 95.25));
+    }
+
+    #[test]
+    fn pixel_round_trip() {
+        let full = PPanoData::centered(800, 400);
+        //GPano记录的局部全景，原图位于画布右上方
+        let cropped = PPanoData {
+            full_width: 800,
+            full_height: 400,
+            cropped_width: 300,
+            cropped_height: 120,
+            cropped_x: 450,
+            cropped_y: 60,
+            pose_pitch: None,
+            pose_roll: None,
+        };
+        for panodata in [full, cropped] {
+            for (x, y) in [(0.5, 0.5), (123.5, 45.25), (299.0, 119.75)] {
+                let (yaw, pitch) = pixel_to_angles(x, y, &panodata);
+                let (px, py) = angles_to_pixel(yaw, pitch, &panodata);
+                assert!(close(px, x) && close(py, y), "{:?} {} {}", panodata, x, y);
+            }
+        }
+        //画布中心位于原图左侧的补齐区域
+        let (x, y) = angles_to_pixel(0.0, 0.0, &cropped);
+        assert!(close(x, -50.0) && close(y, 140.0));
+    }
+
+    #[test]
+    fn vector_directions() {
+        assert_eq!(vector_to_angles(0.0, 0.0, 1.0), (0.0, 0.0));
+        assert_eq!(vector_to_angles(1.0, 0.0, 0.0), (PI / 2.0, 0.0));
+        assert_eq!(vector_to_angles(0.0, 1.0, 0.0), (0.0, PI / 2.0));
+        assert!(close(vector_to_angles(-1.0, 0.0, -1.0).0, -3.0 * PI / 4.0));
+    }
+
+    #[test]
+    fn bearing_round_trip() {
+        let offset = 30f64.to_radians();
+        assert!(close(yaw_to_bearing(0.0, offset), 30.0));
+        assert!(close(yaw_to_bearing(-PI / 2.0, offset), 300.0));
+        assert!(close(bearing_to_yaw(30.0, offset), 0.0));
+        assert!(close(bearing_to_yaw(350.0, offset), (-40f64).to_radians()));
+        for bearing in [0.0, 45.0, 179.0, 181.0, 359.5] {
+            assert!(close(
+                yaw_to_bearing(bearing_to_yaw(bearing, offset), offset),
+                bearing
+            ));
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//各模块测试共用的数据构造
+use crate::encode::{EncodeOptions, OutputFormat};
+use crate::tile::{SmallMode, TileMode, TileOptions};
+use crate::PImage;
+use exif::experimental::Writer;
+use exif::{Field, In, Rational, Tag, Value};
+use image::{Rgb, RgbImage};
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// 新建空的临时目录，name需在全部测试中唯一
+pub fn temp_folder(name: &str) -> PathBuf {
+    let folder = std::env::temp_dir().join(format!("pbuildtool-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&folder);
+    fs::create_dir_all(&folder).unwrap();
+    folder
+}
+
+/// 输出PNG的切片参数，模板为r{row}-c{col}.png，不输出立方体贴图
+pub fn tile_options(mode: TileMode, levels: u32) -> TileOptions {
+    TileOptions {
+        rows: None,
+        cols: None,
+        tilesize: None,
+        template: "r{row}-c{col}.png".to_string(),
+        levels,
+        mode,
+        small: SmallMode::Single,
+        cubemap: false,
+        facesize: None,
+        cubetiles: 1,
+        encode: EncodeOptions {
+            format: OutputFormat::Png,
+            ..Default::default()
+        },
+    }
+}
+
+/// 将exif字段写为TIFF结构的exif数据
+pub fn exif_data(fields: &[Field]) -> Vec<u8> {
+    let mut writer = Writer::new();
+    for field in fields {
+        writer.push_field(field);
+    }
+    let mut buf = Cursor::new(Vec::new());
+    writer.write(&mut buf, false).unwrap();
+    buf.into_inner()
+}
+
+/// GPSImgDirection及其参照方向，方向以百分之一度为单位
+pub fn direction_fields(hundredths: u32, reference: &str) -> [Field; 2] {
+    [
+        Field {
+            tag: Tag::GPSImgDirection,
+            ifd_num: In::PRIMARY,
+            value: Value::Rational(vec![Rational {
+                num: hundredths,
+                denom: 100,
+            }]),
+        },
+        Field {
+            tag: Tag::GPSImgDirectionRef,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![reference.as_bytes().to_vec()]),
+        },
+    ]
+}
+
+/// 写入200x100的JPEG全景图，exif数据放在APP1段中
+pub fn write_jpeg_with_exif(path: &Path, exif: &[u8]) {
+    let mut jpeg = Cursor::new(Vec::new());
+    RgbImage::from_pixel(200, 100, Rgb([40, 80, 120]))
+        .write_to(&mut jpeg, image::ImageFormat::Jpeg)
+        .unwrap();
+    let jpeg = jpeg.into_inner();
+    //在SOI之后插入APP1 exif段
+    let length = (2 + 6 + exif.len()) as u16;
+    let mut data = jpeg[..2].to_vec();
+    data.extend_from_slice(&[0xFF, 0xE1]);
+    data.extend_from_slice(&length.to_be_bytes());
+    data.extend_from_slice(b"Exif\0\0");
+    data.extend_from_slice(exif);
+    data.extend_from_slice(&jpeg[2..]);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, data).unwrap();
+}
+
+/// 只有id与缩略图的全景图，id为"分组/图片名"
+pub fn image(id: &str) -> PImage {
+    let (group, name) = id.rsplit_once('/').unwrap_or(("", id));
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "imagename": name,
+        "usetile": false,
+        "levels": [],
+        "thumbnail": format!("{}/{}/{}_low.JPG", group, name, name),
+        "previews": [],
+        "format": "jpeg",
+        "extension": "jpg",
+        "markers": [],
+        "links": [],
+    }))
+    .unwrap()
+}
@@ -0,0 +1,726 @@
+use clap::ValueEnum;
+use image::imageops::FilterType;
+use image::{imageops, GenericImage, GenericImageView, Rgb, RgbImage};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+
+use crate::encode::EncodeOptions;
+use crate::xmp::GPano;
+
+pub const DEFAULT_ROWS: u32 = 4;
+pub const DEFAULT_COLS: u32 = 8;
+pub const DEFAULT_TEMPLATE: &str = "row-{row}-column-{col}.jpg";
+
+/// 宽高不能被行列数整除时的处理方式
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TileMode {
+    /// 舍弃除不尽的像素（旧版行为）
+    #[default]
+    Truncate,
+    /// 重采样到最接近的可整除尺寸
+    Resample,
+    /// 最后一行、一列切片延伸到图像边缘，覆盖全部像素
+    Edge,
+}
+
+/// 尺寸不足MIN_SIZE的全景图的处理方式
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmallMode {
+    /// 不切片，补齐为2:1后输出单张全景图
+    #[default]
+    Single,
+    /// 放大到MIN_SIZE后正常切片
+    Upscale,
+}
+
+/// 切片布局参数，行列数与切片大小三者可任选其一，未指定时默认8列4行
+#[derive(Serialize, Debug, Clone)]
+pub struct TileOptions {
+    pub rows: Option<u32>,
+    pub cols: Option<u32>,
+    pub tilesize: Option<u32>,
+    /// 切片文件名模板，{row}、{col}为从1开始的行列号
+    pub template: String,
+    pub levels: u32,
+    pub mode: TileMode,
+    pub small: SmallMode,
+    /// 是否额外输出立方体贴图
+    pub cubemap: bool,
+    /// 立方体每个面的边长，默认为全景图宽度的1/4
+    pub facesize: Option<u32>,
+    /// 立方体每个面每边的切片数
+    pub cubetiles: u32,
+    /// 切片、缩略图等图片的编码参数，模板的扩展名需与输出格式一致
+    pub encode: EncodeOptions,
+}
+
+impl TileOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.rows == Some(0)
+            || self.cols == Some(0)
+            || self.tilesize == Some(0)
+            || self.facesize == Some(0)
+        {
+            return Err("切片行列数及切片大小必须大于0".to_string());
+        }
+        if !self.template.contains("{row}") || !self.template.contains("{col}") {
+            return Err(format!(
+                "切片命名模板{}需同时包含{{row}}和{{col}}",
+                self.template
+            ));
+        }
+        //切片只能写在本级目录中
+        if self.template.contains(['/', '\\']) || self.template.contains("..") {
+            return Err(format!(
+                "切片命名模板{}不能包含路径分隔符或..",
+                self.template
+            ));
+        }
+        Ok(())
+    }
+
+    /// 根据全景图宽度计算行列数，返回(行数,列数)
+    pub fn grid(&self, width: u32) -> (u32, u32) {
+        match (self.rows, self.cols, self.tilesize) {
+            (Some(rows), Some(cols), _) => (rows, cols),
+            (Some(rows), None, _) => (rows, rows * 2),
+            (None, Some(cols), _) => ((cols / 2).max(1), cols),
+            (None, None, Some(tilesize)) => {
+                let cols = ((width + tilesize / 2) / tilesize).max(2) & !1;
+                (cols / 2, cols)
+            }
+            (None, None, None) => (DEFAULT_ROWS, DEFAULT_COLS),
+        }
+    }
+
+    pub fn tile_name(&self, row: u32, col: u32) -> String {
+        self.template
+            .replace("{row}", &row.to_string())
+            .replace("{col}", &col.to_string())
+    }
+}
+
+/// 金字塔中的一级切片，level 0 为原始分辨率，每增加一级宽高减半
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PLevel {
+    pub level: u32,
+    pub width: u32,
+    pub height: u32,
+    pub cols: u32,
+    pub rows: u32,
+    /// 切片宽高，edge模式下最后一列、一行切片会补齐剩余像素
+    pub tilewidth: u32,
+    pub tileheight: u32,
+    /// 切片所在目录，相对于输出根目录
+    pub path: String,
+    /// 切片文件名模板
+    pub template: String,
+}
+
+/// 原图在完整全景画布中的位置，字段与Photo Sphere Viewer的panoData一致
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PPanoData {
+    pub full_width: u32,
+    pub full_height: u32,
+    pub cropped_width: u32,
+    pub cropped_height: u32,
+    pub cropped_x: u32,
+    pub cropped_y: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pose_pitch: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pose_roll: Option<f64>,
+}
+
+impl PPanoData {
+    /// 按2:1补齐画布，原图居中放置
+    pub fn centered(width: u32, height: u32) -> PPanoData {
+        let full_width = width.max(height * 2);
+        let full_height = (full_width / 2).max(height);
+        PPanoData {
+            full_width,
+            full_height,
+            cropped_width: width,
+            cropped_height: height,
+            cropped_x: (full_width - width) / 2,
+            cropped_y: (full_height - height) / 2,
+            pose_pitch: None,
+            pose_roll: None,
+        }
+    }
+
+    /// 按XMP GPano信息放置原图，图像被缩放过时按实际宽度等比换算，信息无效时返回None
+    pub fn from_gpano(gpano: &GPano, width: u32, height: u32) -> Option<PPanoData> {
+        if gpano.cropped_width == 0 || gpano.full_width == 0 {
+            return None;
+        }
+        let scale = |v: u32| (v as u64 * width as u64 / gpano.cropped_width as u64) as u32;
+        let panodata = PPanoData {
+            full_width: scale(gpano.full_width),
+            full_height: scale(gpano.full_height),
+            cropped_width: width,
+            cropped_height: height,
+            cropped_x: scale(gpano.cropped_left),
+            cropped_y: scale(gpano.cropped_top),
+            pose_pitch: gpano.pose_pitch,
+            pose_roll: gpano.pose_roll,
+        };
+        //完整画布需为2:1，且原图不超出画布
+        if panodata.full_width.abs_diff(panodata.full_height * 2) > 2
+            || panodata.cropped_x + width > panodata.full_width
+            || panodata.cropped_y + height > panodata.full_height
+        {
+            return None;
+        }
+        Some(panodata)
+    }
+
+    /// 是否需要写入索引，完整且无姿态修正的全景图不写入
+    pub fn is_needed(&self) -> bool {
+        !self.is_full() || self.pose_pitch.is_some() || self.pose_roll.is_some()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.cropped_width == self.full_width && self.cropped_height == self.full_height
+    }
+
+    /// 按比例缩放，用于放大后的全景图
+    pub fn scaled(&self, full_width: u32) -> PPanoData {
+        let scale = |v: u32| (v as u64 * full_width as u64 / self.full_width as u64) as u32;
+        PPanoData {
+            full_width,
+            full_height: scale(self.full_height),
+            cropped_width: scale(self.cropped_width),
+            cropped_height: scale(self.cropped_height),
+            cropped_x: scale(self.cropped_x),
+            cropped_y: scale(self.cropped_y),
+            ..*self
+        }
+    }
+}
+
+/// 按panodata补齐为2:1的全景图，不复制原图，原图以外的区域读取时按黑色处理
+pub struct PaddedImage<'a> {
+    img: Cow<'a, RgbImage>,
+    /// 原图在画布中的位置
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl<'a> PaddedImage<'a> {
+    pub fn new(img: &'a RgbImage, panodata: &PPanoData) -> PaddedImage<'a> {
+        PaddedImage {
+            img: Cow::Borrowed(img),
+            x: panodata.cropped_x,
+            y: panodata.cropped_y,
+            width: panodata.full_width,
+            height: panodata.full_height,
+        }
+    }
+
+    pub fn owned(img: RgbImage, panodata: &PPanoData) -> PaddedImage<'static> {
+        PaddedImage {
+            img: Cow::Owned(img),
+            x: panodata.cropped_x,
+            y: panodata.cropped_y,
+            width: panodata.full_width,
+            height: panodata.full_height,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> Rgb<u8> {
+        match (x.checked_sub(self.x), y.checked_sub(self.y)) {
+            (Some(sx), Some(sy)) if sx < self.img.width() && sy < self.img.height() => {
+                *self.img.get_pixel(sx, sy)
+            }
+            _ => Rgb([0, 0, 0]),
+        }
+    }
+
+    /// 复制画布中的矩形区域，只复制与原图重叠的部分
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> RgbImage {
+        let mut tile = RgbImage::new(width, height);
+        let x0 = x.max(self.x);
+        let y0 = y.max(self.y);
+        let x1 = (x + width).min(self.x + self.img.width());
+        let y1 = (y + height).min(self.y + self.img.height());
+        if x0 < x1 && y0 < y1 {
+            let region = self.img.view(x0 - self.x, y0 - self.y, x1 - x0, y1 - y0);
+            tile.copy_from(&*region, x0 - x, y0 - y).unwrap();
+        }
+        tile
+    }
+
+    /// 输出完整的画布
+    pub fn to_image(&self) -> RgbImage {
+        if self.x == 0 && self.y == 0 && self.img.dimensions() == (self.width, self.height) {
+            return self.img.clone().into_owned();
+        }
+        self.crop(0, 0, self.width, self.height)
+    }
+
+    /// 将画布左上角cover_width x cover_height的区域缩放为width x height，只缩放原图部分
+    pub fn resize(
+        &self,
+        cover_width: u32,
+        cover_height: u32,
+        width: u32,
+        height: u32,
+        filter: FilterType,
+    ) -> PaddedImage<'static> {
+        let sx = width as f64 / cover_width as f64;
+        let sy = height as f64 / cover_height as f64;
+        let x0 = self.x.min(cover_width);
+        let y0 = self.y.min(cover_height);
+        let x1 = (self.x + self.img.width()).min(cover_width);
+        let y1 = (self.y + self.img.height()).min(cover_height);
+        let nx0 = (x0 as f64 * sx).round() as u32;
+        let ny0 = (y0 as f64 * sy).round() as u32;
+        let nx1 = ((x1 as f64 * sx).round() as u32).min(width);
+        let ny1 = ((y1 as f64 * sy).round() as u32).min(height);
+        let img = if nx1 > nx0 && ny1 > ny0 {
+            imageops::resize(
+                &*self.img.view(x0 - self.x, y0 - self.y, x1 - x0, y1 - y0),
+                nx1 - nx0,
+                ny1 - ny0,
+                filter,
+            )
+        } else {
+            RgbImage::new(0, 0)
+        };
+        PaddedImage {
+            img: Cow::Owned(img),
+            x: nx0,
+            y: ny0,
+            width,
+            height,
+        }
+    }
+}
+
+/// 输出多级切片，层级行列数逐级减半，行数不足1时停止
+pub fn clip_pyramid(
+    img: &PaddedImage,
+    options: &TileOptions,
+    folder: &Path,
+    relpath: &str,
+) -> Result<Vec<PLevel>, Box<dyn std::error::Error>> {
+    //行列数不能超过图像的像素数，否则切片宽高为0
+    let (grid_rows, grid_cols) = options.grid(img.width());
+    let rows = grid_rows.min(img.height()).max(1);
+    let cols = grid_cols.min(img.width()).max(1);
+    if (rows, cols) != (grid_rows, grid_cols) {
+        println!(
+            "图像{}x{}不足{}行{}列切片，按{}行{}列切片",
+            img.width(),
+            img.height(),
+            grid_rows,
+            grid_cols,
+            rows,
+            cols
+        );
+    }
+    let resampled;
+    let base = if options.mode == TileMode::Resample {
+        let _width = ((img.width() + cols / 2) / cols).max(1) * cols;
+        let _height = ((img.height() + rows / 2) / rows).max(1) * rows;
+        resampled = img.resize(
+            img.width(),
+            img.height(),
+            _width,
+            _height,
+            FilterType::Lanczos3,
+        );
+        &resampled
+    } else {
+        img
+    };
+    let tilewidth = base.width() / cols;
+    let tileheight = base.height() / rows;
+    //truncate模式下只有切片覆盖的区域参与输出
+    let (_cover_width, _cover_height) = if options.mode == TileMode::Edge {
+        (base.width(), base.height())
+    } else {
+        (cols * tilewidth, rows * tileheight)
+    };
+
+    let mut result = Vec::new();
+    for level in 0..options.levels.max(1) {
+        //每级宽高为上一级的一半，保持2:1，按原始切片大小重新计算行列数，末行末列切片补齐剩余像素
+        let _width = _cover_width.div_ceil(1 << level);
+        let _height = _cover_height.div_ceil(1 << level);
+        if level > 0 && (_height < tileheight || _width < tilewidth) {
+            println!("图像不足一行切片，金字塔止于第{}级", level);
+            break;
+        }
+        let (_rows, _cols) = if level == 0 {
+            (rows, cols)
+        } else {
+            (
+                ((_height + tileheight / 2) / tileheight).max(1),
+                ((_width + tilewidth / 2) / tilewidth).max(1),
+            )
+        };
+        let (level_folder, level_path) = if level == 0 {
+            //原始分辨率保持原有目录结构
+            (folder.to_path_buf(), relpath.to_string())
+        } else {
+            (
+                folder.join(format!("l{}", level)),
+                format!("{}/l{}", relpath, level),
+            )
+        };
+        fs::create_dir_all(&level_folder)?;
+        let level_img = if level == 0 {
+            None
+        } else {
+            Some(base.resize(
+                _cover_width,
+                _cover_height,
+                _width,
+                _height,
+                FilterType::Triangle,
+            ))
+        };
+        let level = PLevel {
+            level,
+            width: _width,
+            height: _height,
+            cols: _cols,
+            rows: _rows,
+            tilewidth,
+            tileheight,
+            path: level_path,
+            template: options.template.clone(),
+        };
+        clip_grid(
+            level_img.as_ref().unwrap_or(base),
+            &level,
+            options,
+            &level_folder,
+        )?;
+        result.push(level);
+    }
+    Ok(result)
+}
+
+/// 计算切片在该级图像中的位置与大小，返回(x,y,宽,高)
+pub fn tile_rect(level: &PLevel, row: u32, col: u32) -> (u32, u32, u32, u32) {
+    let x = col * level.tilewidth;
+    let y = row * level.tileheight;
+    let w = if col + 1 == level.cols {
+        level.width - x
+    } else {
+        level.tilewidth
+    };
+    let h = if row + 1 == level.rows {
+        level.height - y
+    } else {
+        level.tileheight
+    };
+    (x, y, w, h)
+}
+
+/// 将图片按行列切片输出，任一切片写出失败时返回错误
+fn clip_grid(
+    img: &PaddedImage,
+    level: &PLevel,
+    options: &TileOptions,
+    folder: &Path,
+) -> Result<(), String> {
+    (0..level.rows * level.cols)
+        .into_par_iter()
+        .map(|x| -> Result<(), String> {
+            let i = x / level.cols;
+            let j = x % level.cols;
+
+            let (x, y, w, h) = tile_rect(level, i, j);
+            let regionimgbuf = img.crop(x, y, w, h);
+            let newfilename = folder.join(options.tile_name(i + 1, j + 1));
+            println!("{}文件导出", newfilename.to_str().unwrap());
+            options
+                .encode
+                .save(&regionimgbuf, &newfilename)
+                .map_err(|e| format!("切片{}写出失败：{}", newfilename.display(), e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{temp_folder, tile_options as options};
+    use image::Rgb;
+
+    //复制补齐的参照实现
+    fn pad_equirect(img: &RgbImage, panodata: &PPanoData) -> RgbImage {
+        if panodata.is_full() {
+            return img.clone();
+        }
+        let mut imgbuf = RgbImage::new(panodata.full_width, panodata.full_height);
+        imgbuf
+            .sub_image(
+                panodata.cropped_x,
+                panodata.cropped_y,
+                img.width(),
+                img.height(),
+            )
+            .copy_from(img, 0, 0)
+            .unwrap();
+        imgbuf
+    }
+
+    fn sample(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            Rgb([
+                (x % 251) as u8,
+                (y % 241) as u8,
+                ((x * 7 + y * 13) % 256) as u8,
+            ])
+        })
+    }
+
+    fn reassemble(folder: &Path, level: &PLevel, options: &TileOptions) -> RgbImage {
+        let mut canvas = RgbImage::new(level.width, level.height);
+        for i in 0..level.rows {
+            for j in 0..level.cols {
+                let (x, y, w, h) = tile_rect(level, i, j);
+                let tile = image::open(folder.join(options.tile_name(i + 1, j + 1)))
+                    .unwrap()
+                    .into_rgb8();
+                assert_eq!((tile.width(), tile.height()), (w, h));
+                canvas.copy_from(&tile, x, y).unwrap();
+            }
+        }
+        canvas
+    }
+
+    fn gpano(full_width: u32, full_height: u32, cropped_top: u32) -> GPano {
+        GPano {
+            cropped_width: 8000,
+            cropped_height: 3000,
+            full_width,
+            full_height,
+            cropped_left: 0,
+            cropped_top,
+            pose_pitch: Some(2.5),
+            pose_roll: None,
+        }
+    }
+
+    #[test]
+    fn gpano_scales_to_actual_width() {
+        //GPano按8000宽记录，实际图片缩小为2000宽
+        let panodata = PPanoData::from_gpano(&gpano(8000, 4000, 500), 2000, 750).unwrap();
+        assert_eq!((panodata.full_width, panodata.full_height), (2000, 1000));
+        assert_eq!(
+            (panodata.cropped_width, panodata.cropped_height),
+            (2000, 750)
+        );
+        assert_eq!((panodata.cropped_x, panodata.cropped_y), (0, 125));
+        assert_eq!(panodata.pose_pitch, Some(2.5));
+        assert!(panodata.is_needed() && !panodata.is_full());
+        let same = PPanoData::from_gpano(&gpano(8000, 4000, 500), 8000, 3000).unwrap();
+        assert_eq!(same.cropped_y, 500);
+    }
+
+    #[test]
+    fn gpano_rejects_invalid_canvas() {
+        //画布不是2:1
+        assert!(PPanoData::from_gpano(&gpano(8000, 3000, 0), 8000, 3000).is_none());
+        //原图超出画布
+        assert!(PPanoData::from_gpano(&gpano(8000, 4000, 1500), 8000, 3000).is_none());
+        let empty = GPano {
+            cropped_width: 0,
+            ..gpano(8000, 4000, 0)
+        };
+        assert!(PPanoData::from_gpano(&empty, 8000, 3000).is_none());
+    }
+
+    #[test]
+    fn pad_keeps_source_centered() {
+        let img = sample(203, 61);
+        let padded = pad_equirect(&img, &PPanoData::centered(203, 61));
+        assert_eq!((padded.width(), padded.height()), (203, 101));
+        assert_eq!(padded.get_pixel(5, 20), img.get_pixel(5, 0));
+        assert_eq!(padded.get_pixel(0, 0), &Rgb([0, 0, 0]));
+
+        let tall = sample(100, 80);
+        let padded = pad_equirect(&tall, &PPanoData::centered(100, 80));
+        assert_eq!((padded.width(), padded.height()), (160, 80));
+        assert_eq!(padded.get_pixel(30, 0), tall.get_pixel(0, 0));
+    }
+
+    #[test]
+    fn template_stays_in_level_folder() {
+        let template = |template: &str| TileOptions {
+            template: template.to_string(),
+            ..options(TileMode::Edge, 1)
+        };
+        assert!(template("r{row}-c{col}.png").validate().is_ok());
+        assert!(template("r{row}.png").validate().is_err());
+        assert!(template("sub/r{row}-c{col}.png").validate().is_err());
+        assert!(template("sub\\r{row}-c{col}.png").validate().is_err());
+        assert!(template("..r{row}-c{col}.png").validate().is_err());
+    }
+
+    #[test]
+    fn tile_write_error_fails_pyramid() {
+        let img = sample(200, 100);
+        let panodata = PPanoData::centered(200, 100);
+        let options = options(TileMode::Truncate, 1);
+        assert!(options.validate().is_ok());
+        let folder = temp_folder("write-error");
+        //切片路径被同名目录占用
+        fs::create_dir_all(folder.join(options.tile_name(2, 3))).unwrap();
+        let result = clip_pyramid(&PaddedImage::new(&img, &panodata), &options, &folder, "g/p");
+        assert!(result.is_err());
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn grid_larger_than_image_is_clamped() {
+        let img = sample(6, 3);
+        let panodata = PPanoData::centered(6, 3);
+        for mode in [TileMode::Edge, TileMode::Resample, TileMode::Truncate] {
+            let options = TileOptions {
+                rows: Some(16),
+                cols: Some(32),
+                ..options(mode, 2)
+            };
+            let folder = temp_folder("tiny");
+            let levels =
+                clip_pyramid(&PaddedImage::new(&img, &panodata), &options, &folder, "g/p").unwrap();
+            assert_eq!((levels[0].rows, levels[0].cols), (3, 6), "{:?}", mode);
+            assert_eq!((levels[0].tilewidth, levels[0].tileheight), (1, 1));
+            for (level, folder) in levels.iter().zip([folder.clone(), folder.join("l1")]) {
+                let canvas = reassemble(&folder, level, &options);
+                assert_eq!(
+                    (canvas.width(), canvas.height()),
+                    (level.width, level.height)
+                );
+            }
+            let _ = fs::remove_dir_all(&folder);
+        }
+    }
+
+    #[test]
+    fn virtual_padding_matches_padded_copy() {
+        let img = sample(150, 40);
+        let panodata = PPanoData::centered(150, 40);
+        let padded = pad_equirect(&img, &panodata);
+        let virtual_padded = PaddedImage::new(&img, &panodata);
+        assert_eq!(virtual_padded.to_image(), padded);
+        for (x, y, w, h) in [
+            (0, 0, 150, 75),
+            (10, 5, 30, 20),
+            (140, 60, 10, 15),
+            (0, 0, 1, 1),
+        ] {
+            assert_eq!(
+                virtual_padded.crop(x, y, w, h),
+                padded.view(x, y, w, h).to_image(),
+                "{} {} {} {}",
+                x,
+                y,
+                w,
+                h
+            );
+        }
+        assert_eq!(virtual_padded.get_pixel(20, 30), *padded.get_pixel(20, 30));
+    }
+
+    #[test]
+    fn edge_tiles_reproduce_padded_image() {
+        let img = sample(203, 91);
+        let panodata = PPanoData::centered(203, 91);
+        let padded = pad_equirect(&img, &panodata);
+        let options = options(TileMode::Edge, 2);
+        let folder = temp_folder("tile-edge");
+        let levels =
+            clip_pyramid(&PaddedImage::new(&img, &panodata), &options, &folder, "g/p").unwrap();
+        assert_eq!(levels.len(), 2);
+        assert_eq!(reassemble(&folder, &levels[0], &options), padded);
+        assert_eq!((levels[1].width, levels[1].height), (102, 51));
+        let lower = reassemble(&folder.join("l1"), &levels[1], &options);
+        assert_eq!((lower.width(), lower.height()), (102, 51));
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn lower_levels_stay_two_to_one() {
+        let img = sample(600, 300);
+        let panodata = PPanoData::centered(600, 300);
+        for mode in [TileMode::Edge, TileMode::Resample, TileMode::Truncate] {
+            //3行时下一级行数不能整除
+            let options = TileOptions {
+                rows: Some(3),
+                cols: Some(6),
+                ..options(mode, 3)
+            };
+            let folder = temp_folder("tile-levels");
+            let levels =
+                clip_pyramid(&PaddedImage::new(&img, &panodata), &options, &folder, "g/p").unwrap();
+            assert_eq!(levels.len(), 2);
+            for (level, folder) in levels.iter().zip([folder.clone(), folder.join("l1")]) {
+                assert_eq!(level.width, level.height * 2, "{:?}", mode);
+                let canvas = reassemble(&folder, level, &options);
+                assert_eq!(
+                    (canvas.width(), canvas.height()),
+                    (level.width, level.height)
+                );
+            }
+            assert_eq!(
+                (levels[1].width, levels[1].rows, levels[1].cols),
+                (300, 2, 3)
+            );
+            let _ = fs::remove_dir_all(&folder);
+        }
+    }
+
+    #[test]
+    fn resample_tiles_are_uniform() {
+        let img = sample(203, 101);
+        let panodata = PPanoData::centered(203, 101);
+        let padded = pad_equirect(&img, &panodata);
+        let options = options(TileMode::Resample, 1);
+        let folder = temp_folder("tile-resample");
+        let levels =
+            clip_pyramid(&PaddedImage::new(&img, &panodata), &options, &folder, "g/p").unwrap();
+        let level = &levels[0];
+        assert_eq!((level.width, level.height), (200, 100));
+        assert_eq!(level.width, level.cols * level.tilewidth);
+        assert_eq!(level.height, level.rows * level.tileheight);
+        let resampled = imageops::resize(&padded, 200, 100, FilterType::Lanczos3);
+        assert_eq!(reassemble(&folder, level, &options), resampled);
+        let _ = fs::remove_dir_all(&folder);
+    }
+
+    #[test]
+    fn truncate_drops_remainder() {
+        let img = sample(203, 101);
+        let panodata = PPanoData::centered(203, 101);
+        let options = options(TileMode::Truncate, 1);
+        let folder = temp_folder("tile-truncate");
+        let levels =
+            clip_pyramid(&PaddedImage::new(&img, &panodata), &options, &folder, "g/p").unwrap();
+        assert_eq!((levels[0].width, levels[0].height), (200, 100));
+        let _ = fs::remove_dir_all(&folder);
+    }
+}
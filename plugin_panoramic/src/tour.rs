@@ -0,0 +1,145 @@
+use crate::geodesy;
+use crate::sphere;
+use serde::{Deserialize, Serialize};
+
+/// 漫游连接，字段与Photo Sphere Viewer虚拟漫游插件的link一致
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PLink {
+    /// 目标全景图id
+    pub node_id: String,
+    /// 连接箭头在本全景图中的经度，单位弧度，已按本全景图的方位角修正，
+    /// 本全景图朝向未知时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    /// 到目标全景图的距离，单位米
+    pub distance: f64,
+    /// 到目标全景图的真北方位角，单位度
+    pub bearing: f64,
+}
+
+/// 参与连接计算的全景图位置
+pub struct TourNode<'a> {
+    pub id: &'a str,
+    pub lon: f64,
+    pub lat: f64,
+    /// 全景图中心的真北方位角，单位度
+    pub heading: Option<f64>,
+}
+
+/// 计算全景图之间的漫游连接，max_distance为最大连接距离(米)，nearest为每张全景图最多连接的邻近数量，
+/// 单向满足条件的连接会补齐为双向
+pub fn compute_links(
+    nodes: &[TourNode],
+    max_distance: Option<f64>,
+    nearest: Option<usize>,
+) -> Vec<Vec<PLink>> {
+    let count = nodes.len();
+    let mut linked = vec![vec![false; count]; count];
+    for i in 0..count {
+        let mut candidates: Vec<(usize, f64)> = (0..count)
+            .filter(|&j| j != i)
+            .map(|j| {
+                let d = geodesy::distance(nodes[i].lon, nodes[i].lat, nodes[j].lon, nodes[j].lat);
+                (j, d)
+            })
+            .filter(|&(_, d)| max_distance.is_none_or(|max| d <= max))
+            .collect();
+        candidates.sort_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some(k) = nearest {
+            candidates.truncate(k);
+        }
+        for (j, _) in candidates {
+            linked[i][j] = true;
+            linked[j][i] = true;
+        }
+    }
+
+    (0..count)
+        .map(|i| {
+            let from = &nodes[i];
+            (0..count)
+                .filter(|&j| linked[i][j])
+                .map(|j| {
+                    let to = &nodes[j];
+                    let bearing = geodesy::bearing(from.lon, from.lat, to.lon, to.lat);
+                    PLink {
+                        node_id: to.id.to_string(),
+                        longitude: from
+                            .heading
+                            .map(|heading| sphere::bearing_to_yaw(bearing, heading.to_radians())),
+                        distance: geodesy::distance(from.lon, from.lat, to.lon, to.lat),
+                        bearing,
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    //沿纬线向东排列，与第一张的距离依次约为0、100、350、1000米
+    fn nodes() -> Vec<TourNode<'static>> {
+        let lat = 39.9f64;
+        let degrees =
+            |meters: f64| (meters / (geodesy::EARTH_RADIUS * lat.to_radians().cos())).to_degrees();
+        [("a", 0.0), ("b", 100.0), ("c", 350.0), ("d", 1000.0)]
+            .into_iter()
+            .map(|(id, meters)| TourNode {
+                id,
+                lon: 116.4 + degrees(meters),
+                lat,
+                heading: None,
+            })
+            .collect()
+    }
+
+    fn targets(links: &[PLink]) -> Vec<&str> {
+        links.iter().map(|link| link.node_id.as_str()).collect()
+    }
+
+    #[test]
+    fn max_distance_filters_links() {
+        let links = compute_links(&nodes(), Some(200.0), None);
+        assert_eq!(targets(&links[0]), ["b"]);
+        assert_eq!(targets(&links[1]), ["a"]);
+        assert!(links[2].is_empty() && links[3].is_empty());
+        assert!((links[0][0].distance - 100.0).abs() < 0.5);
+
+        let links = compute_links(&nodes(), None, None);
+        assert!(links.iter().all(|links| links.len() == 3));
+    }
+
+    #[test]
+    fn nearest_links_become_two_way() {
+        let links = compute_links(&nodes(), None, Some(1));
+        //a、b互为最近，c最近为b，d最近为c，补齐为双向后b、c各有两个连接
+        assert_eq!(targets(&links[0]), ["b"]);
+        assert_eq!(targets(&links[1]), ["a", "c"]);
+        assert_eq!(targets(&links[2]), ["b", "d"]);
+        assert_eq!(targets(&links[3]), ["c"]);
+    }
+
+    #[test]
+    fn longitude_follows_heading() {
+        let mut nodes = nodes();
+        let links = compute_links(&nodes, None, Some(1));
+        //朝向未知时无法确定箭头位置
+        assert!((links[0][0].bearing - 90.0).abs() < 0.01);
+        assert!(links[0][0].longitude.is_none());
+
+        //正东方向，朝向正北时位于全景图中心右侧90度
+        nodes[0].heading = Some(0.0);
+        nodes[1].heading = Some(120.0);
+        nodes[2].heading = Some(90.0);
+        let links = compute_links(&nodes, None, Some(1));
+        assert!((links[0][0].longitude.unwrap() - PI / 2.0).abs() < 1e-3);
+        assert!((links[1][0].longitude.unwrap() - 150f64.to_radians()).abs() < 1e-3);
+        assert!(links[2][0].longitude.unwrap().abs() > 3.0);
+        assert!(links[3][0].longitude.is_none());
+    }
+}
@@ -0,0 +1,301 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// 全景图位置的来源
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LocationSource {
+    /// exif中的GPS信息
+    Exif,
+    /// 按拍摄时间在轨迹中插值
+    Track,
+}
+
+/// 轨迹点，time为UTC的Unix时间（秒）
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackPoint {
+    pub time: f64,
+    pub lon: f64,
+    pub lat: f64,
+    pub altitude: Option<f64>,
+}
+
+/// 按时间排序的GPS轨迹
+#[derive(Debug, Clone)]
+pub struct Track {
+    points: Vec<TrackPoint>,
+}
+
+/// 公历日期时间转为Unix时间（秒），不含时区
+pub fn unix_seconds(year: i64, month: u32, day: u32, hour: u32, minute: u32, second: f64) -> f64 {
+    //Howard Hinnant的days_from_civil算法
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    (days * 86400 + hour as i64 * 3600 + minute as i64 * 60) as f64 + second
+}
+
+/// 解析时区偏移，如`+08:00`、`-0530`、`+8`、`Z`，返回分钟数
+pub fn parse_timezone(text: &str) -> Result<i32, String> {
+    let text = text.trim();
+    if text.eq_ignore_ascii_case("z") || text.eq_ignore_ascii_case("utc") {
+        return Ok(0);
+    }
+    let error = || format!("时区{}的格式应为+08:00", text);
+    let (sign, rest) = match text.as_bytes().first() {
+        Some(b'+') => (1, &text[1..]),
+        Some(b'-') => (-1, &text[1..]),
+        _ => return Err(error()),
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((h, m)) => (h, m),
+        None if rest.len() == 4 && rest.is_char_boundary(2) => rest.split_at(2),
+        None => (rest, "0"),
+    };
+    let hours: i32 = hours.parse().map_err(|_| error())?;
+    let minutes: i32 = minutes.parse().map_err(|_| error())?;
+    if hours > 14 || minutes >= 60 {
+        return Err(error());
+    }
+    Ok(sign * (hours * 60 + minutes))
+}
+
+//解析ISO 8601时间，如2024-05-01T08:30:15.5Z或2024-05-01T16:30:15+08:00
+fn parse_iso8601(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let (date, time) = text.split_once(['T', ' '])?;
+    let mut date = date.split('-');
+    let year = date.next()?.parse().ok()?;
+    let month = date.next()?.parse().ok()?;
+    let day = date.next()?.parse().ok()?;
+    let (time, offset) = match time.find(['Z', 'z', '+', '-']) {
+        Some(index) => (&time[..index], parse_timezone(&time[index..]).ok()?),
+        None => (time, 0),
+    };
+    let mut time = time.split(':');
+    let hour = time.next()?.parse().ok()?;
+    let minute = time.next()?.parse().ok()?;
+    let second: f64 = time.next().unwrap_or("0").parse().ok()?;
+    Some(unix_seconds(year, month, day, hour, minute, second) - offset as f64 * 60.0)
+}
+
+//属性名前可以是任意空白，格式化的GPX中常为换行或制表符
+fn attribute<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!("{}=", name);
+    let start = element
+        .match_indices(&pattern)
+        .map(|(index, _)| index)
+        .find(|&index| element[..index].ends_with(char::is_whitespace))?
+        + pattern.len();
+    let quote = element[start..].chars().next()?;
+    let value = &element[start + 1..];
+    Some(&value[..value.find(quote)?])
+}
+
+fn child_text<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    let start = element.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = element[start..].find(&format!("</{}>", name))?;
+    Some(element[start..start + end].trim())
+}
+
+//读取GPX中带时间的trkpt
+fn parse_gpx(text: &str) -> Vec<TrackPoint> {
+    text.split("<trkpt")
+        .skip(1)
+        .filter_map(|element| {
+            let element = &element[..element.find("</trkpt>").unwrap_or(element.len())];
+            Some(TrackPoint {
+                time: parse_iso8601(child_text(element, "time")?)?,
+                lon: attribute(element, "lon")?.parse().ok()?,
+                lat: attribute(element, "lat")?.parse().ok()?,
+                altitude: child_text(element, "ele").and_then(|v| v.parse().ok()),
+            })
+        })
+        .collect()
+}
+
+//NMEA的ddmm.mmmm格式转为度
+fn nmea_degrees(value: &str, hemisphere: &str) -> Option<f64> {
+    let value: f64 = value.parse().ok()?;
+    let degrees = (value / 100.0).trunc() + (value % 100.0) / 60.0;
+    match hemisphere {
+        "S" | "W" => Some(-degrees),
+        _ => Some(degrees),
+    }
+}
+
+//读取NMEA的RMC语句，海拔取同一时刻GGA语句中的值
+fn parse_nmea(text: &str) -> Vec<TrackPoint> {
+    let sentences: Vec<Vec<&str>> = text
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| line.starts_with('$'))
+        .map(|line| {
+            line[..line.find('*').unwrap_or(line.len())]
+                .split(',')
+                .collect()
+        })
+        .collect();
+    let altitudes: HashMap<&str, f64> = sentences
+        .iter()
+        .filter(|fields| fields[0].ends_with("GGA") && fields.len() > 9)
+        .filter_map(|fields| Some((fields[1], fields[9].parse().ok()?)))
+        .collect();
+    sentences
+        .iter()
+        .filter(|fields| fields[0].ends_with("RMC") && fields.len() > 9 && fields[2] == "A")
+        .filter_map(|fields| {
+            let (time, date) = (fields[1], fields[9]);
+            if time.len() < 6 || date.len() != 6 {
+                return None;
+            }
+            let number = |text: &str| text.parse::<u32>().ok();
+            let seconds = unix_seconds(
+                2000 + number(&date[4..6])? as i64,
+                number(&date[2..4])?,
+                number(&date[0..2])?,
+                number(&time[0..2])?,
+                number(&time[2..4])?,
+                time[4..].parse().ok()?,
+            );
+            Some(TrackPoint {
+                time: seconds,
+                lat: nmea_degrees(fields[3], fields[4])?,
+                lon: nmea_degrees(fields[5], fields[6])?,
+                altitude: altitudes.get(time).copied(),
+            })
+        })
+        .collect()
+}
+
+impl Track {
+    pub fn new(mut points: Vec<TrackPoint>) -> Track {
+        points.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Track { points }
+    }
+
+    /// 读取GPX或NMEA轨迹文件，内容以<开头时按GPX解析
+    pub fn load(path: &Path) -> Result<Track, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(path)?;
+        let text = text.trim_start_matches('\u{feff}').trim_start();
+        let points = if text.starts_with('<') {
+            parse_gpx(text)
+        } else {
+            parse_nmea(text)
+        };
+        if points.is_empty() {
+            return Err(format!("轨迹文件{}中没有带时间的轨迹点", path.display()).into());
+        }
+        Ok(Track::new(points))
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    /// 按时间在相邻轨迹点之间线性插值，超出轨迹时间范围或相邻轨迹点间隔超过max_gap秒时返回None
+    pub fn interpolate(&self, time: f64, max_gap: f64) -> Option<TrackPoint> {
+        let first = self.points.first()?;
+        let last = self.points.last()?;
+        if time < first.time || time > last.time {
+            return None;
+        }
+        let index = self.points.partition_point(|p| p.time < time);
+        let after = &self.points[index];
+        if index == 0 || after.time == time {
+            return Some(after.clone());
+        }
+        let before = &self.points[index - 1];
+        if after.time - before.time > max_gap {
+            return None;
+        }
+        let t = (time - before.time) / (after.time - before.time);
+        Some(TrackPoint {
+            time,
+            lon: before.lon + (after.lon - before.lon) * t,
+            lat: before.lat + (after.lat - before.lat) * t,
+            altitude: match (before.altitude, after.altitude) {
+                (Some(a), Some(b)) => Some(a + (b - a) * t),
+                (a, b) => a.or(b),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unix_time_and_timezone() {
+        assert_eq!(unix_seconds(1970, 1, 1, 0, 0, 0.0), 0.0);
+        assert_eq!(unix_seconds(2024, 3, 1, 12, 30, 15.0), 1709296215.0);
+        assert_eq!(parse_timezone("+08:00"), Ok(480));
+        assert_eq!(parse_timezone("-0530"), Ok(-330));
+        assert_eq!(parse_timezone("+8"), Ok(480));
+        assert!(parse_timezone("08:00").is_err());
+        //4字节的非ASCII字符不能在第2字节处拆分
+        assert!(parse_timezone("+中0").is_err());
+        assert!(parse_timezone("+😀").is_err());
+        assert_eq!(
+            parse_iso8601("2024-03-01T20:30:15+08:00"),
+            Some(1709296215.0)
+        );
+    }
+
+    #[test]
+    fn parse_gpx_and_nmea() {
+        let gpx = r#"<?xml version="1.0"?><gpx><trk><trkseg>
+            <trkpt lat="39.9" lon="116.4"><ele>50.5</ele><time>2024-03-01T12:30:15Z</time></trkpt>
+            <trkpt lon='116.5' lat='39.8'><time>2024-03-01T12:30:25.5Z</time></trkpt>
+            <trkpt lat="39.7" lon="116.6"></trkpt>
+            <trkpt
+	lat="39.6"	lon="116.7"
+            ><time>2024-03-01T12:30:35Z</time></trkpt>
+            </trkseg></trk></gpx>"#;
+        let points = parse_gpx(gpx);
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[0].altitude, Some(50.5));
+        assert_eq!(points[1].lon, 116.5);
+        assert_eq!(points[1].time, 1709296225.5);
+        //属性名前为换行或制表符
+        assert_eq!((points[2].lon, points[2].lat), (116.7, 39.6));
+
+        let nmea = "$GPGGA,123015.00,3954.0000,N,11624.0000,E,1,08,0.9,48.0,M,,M,,*47\n\
+                    $GPRMC,123015.00,A,3954.0000,N,11624.0000,E,0.0,0.0,010324,,,A*6C\n\
+                    $GNRMC,123016.00,V,,,,,,,010324,,,N*00\n\
+                    $GNRMC,123017.00,A,3954.0600,S,11624.0600,W,0.0,0.0,010324,,,A*6C";
+        let points = parse_nmea(nmea);
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].time, 1709296215.0);
+        assert!((points[0].lat - 39.9).abs() < 1e-9 && (points[0].lon - 116.4).abs() < 1e-9);
+        assert_eq!(points[0].altitude, Some(48.0));
+        assert!((points[1].lat + 39.901).abs() < 1e-9 && points[1].altitude.is_none());
+    }
+
+    #[test]
+    fn interpolate_between_points() {
+        let point = |time: f64, lon: f64| TrackPoint {
+            time,
+            lon,
+            lat: 30.0,
+            altitude: Some(lon),
+        };
+        let track = Track::new(vec![point(20.0, 2.0), point(10.0, 1.0)]);
+        assert_eq!(track.interpolate(15.0, 10.0).unwrap().lon, 1.5);
+        assert_eq!(track.interpolate(15.0, 10.0).unwrap().altitude, Some(1.5));
+        assert_eq!(track.interpolate(10.0, 10.0).unwrap().lon, 1.0);
+        assert_eq!(track.interpolate(20.0, 10.0).unwrap().lon, 2.0);
+        assert!(track.interpolate(9.0, 10.0).is_none());
+        assert!(track.interpolate(21.0, 10.0).is_none());
+        //相邻轨迹点间隔超过max_gap时视为轨迹中断，轨迹点本身仍可使用
+        assert!(track.interpolate(15.0, 5.0).is_none());
+        assert_eq!(track.interpolate(20.0, 5.0).unwrap().lon, 2.0);
+    }
+}
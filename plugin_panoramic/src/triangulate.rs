@@ -0,0 +1,251 @@
+use crate::geodesy::EARTH_RADIUS;
+use crate::PGroup;
+use serde::{Deserialize, Serialize};
+
+//射线之间的最大夹角小于该值（度）时视为平行，无法交会
+const MIN_RAY_ANGLE: f64 = 2.0;
+
+/// 一次观测：拍摄点的WGS84位置及标注点的方向
+#[derive(Debug, Clone)]
+pub struct Observation {
+    pub image: String,
+    pub lon: f64,
+    pub lat: f64,
+    /// 拍摄点海拔，未知时为空
+    pub altitude: Option<f64>,
+    /// 真北方位角，单位度
+    pub bearing: f64,
+    /// 俯仰角，单位弧度
+    pub pitch: f64,
+}
+
+/// 多条方位射线交会的结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Intersection {
+    pub lon: f64,
+    pub lat: f64,
+    /// 目标的海拔，由有海拔的拍摄点按俯仰角推算的平均值
+    pub height: Option<f64>,
+    /// 交会点到各射线的水平垂距的均方根，单位米
+    pub residual: f64,
+}
+
+/// 由多张全景图中同名标注点交会得到的目标
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PObject {
+    pub group: String,
+    #[serde(rename = "type")]
+    pub marker_type: String,
+    pub label: String,
+    /// [经度,纬度]
+    pub lonlat: Vec<f64>,
+    pub height: Option<f64>,
+    pub residual: f64,
+    /// 参与交会的全景图id
+    pub images: Vec<String>,
+}
+
+/// 在拍摄点平均位置的局部平面内求到各射线垂距平方和最小的点，
+/// 射线近似平行或交会点位于某个拍摄点后方时返回错误
+pub fn intersect(observations: &[Observation]) -> Result<Intersection, String> {
+    if observations.len() < 2 {
+        return Err("观测少于2次".to_string());
+    }
+    let count = observations.len() as f64;
+    let lon0 = observations.iter().map(|o| o.lon).sum::<f64>() / count;
+    let lat0 = observations.iter().map(|o| o.lat).sum::<f64>() / count;
+    let east_scale = EARTH_RADIUS * lat0.to_radians().cos();
+    let rays: Vec<([f64; 2], [f64; 2])> = observations
+        .iter()
+        .map(|o| {
+            let origin = [
+                (o.lon - lon0).to_radians() * east_scale,
+                (o.lat - lat0).to_radians() * EARTH_RADIUS,
+            ];
+            let (sin, cos) = o.bearing.to_radians().sin_cos();
+            (origin, [sin, cos])
+        })
+        .collect();
+
+    let max_angle = rays
+        .iter()
+        .flat_map(|a| {
+            rays.iter()
+                .map(move |b| (a.1[0] * b.1[1] - a.1[1] * b.1[0]).abs())
+        })
+        .fold(0.0, f64::max);
+    if max_angle < MIN_RAY_ANGLE.to_radians().sin() {
+        return Err("射线近似平行".to_string());
+    }
+
+    //法方程 Σ(I-ddᵀ)p = Σ(I-ddᵀ)c
+    let (mut a11, mut a12, mut a22, mut b1, mut b2) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for (c, d) in &rays {
+        let (m11, m12, m22) = (1.0 - d[0] * d[0], -d[0] * d[1], 1.0 - d[1] * d[1]);
+        a11 += m11;
+        a12 += m12;
+        a22 += m22;
+        b1 += m11 * c[0] + m12 * c[1];
+        b2 += m12 * c[0] + m22 * c[1];
+    }
+    let det = a11 * a22 - a12 * a12;
+    let p = [(a22 * b1 - a12 * b2) / det, (a11 * b2 - a12 * b1) / det];
+
+    let mut squared = 0.0;
+    let mut heights = Vec::new();
+    for ((c, d), o) in rays.iter().zip(observations) {
+        let v = [p[0] - c[0], p[1] - c[1]];
+        let along = v[0] * d[0] + v[1] * d[1];
+        if along <= 0.0 {
+            return Err(format!("交会点位于全景图{}的后方", o.image));
+        }
+        squared += (v[0] * d[1] - v[1] * d[0]).powi(2);
+        if let Some(altitude) = o.altitude {
+            heights.push(altitude + v[0].hypot(v[1]) * o.pitch.tan());
+        }
+    }
+    Ok(Intersection {
+        lon: lon0 + (p[0] / east_scale).to_degrees(),
+        lat: lat0 + (p[1] / EARTH_RADIUS).to_degrees(),
+        height: if heights.is_empty() {
+            None
+        } else {
+            Some(heights.iter().sum::<f64>() / heights.len() as f64)
+        },
+        residual: (squared / count).sqrt(),
+    })
+}
+
+/// 按分组交会类型及名称相同的标注点，至少需要两张有位置和朝向的全景图，
+/// 返回交会得到的目标及无法交会的目标的原因
+pub fn triangulate(groups: &[PGroup]) -> (Vec<PObject>, Vec<(String, String)>) {
+    let mut objects = Vec::new();
+    let mut failures = Vec::new();
+    for group in groups.iter().flat_map(|group| group.flatten()) {
+        //保持标注点首次出现的顺序
+        let mut targets: Vec<((&str, &str), Vec<Observation>)> = Vec::new();
+        for image in &group.images {
+            let Some(lonlat) = image.position() else {
+                continue;
+            };
+            for marker in &image.markers {
+                let Some(bearing) = marker.bearing else {
+                    continue;
+                };
+                let key = (marker.marker_type.as_str(), marker.label.as_str());
+                let observation = Observation {
+                    image: image.id.clone(),
+                    lon: lonlat[0],
+                    lat: lonlat[1],
+                    altitude: image.height,
+                    bearing,
+                    pitch: marker.pitch,
+                };
+                match targets.iter_mut().find(|(k, _)| *k == key) {
+                    Some((_, observations)) => observations.push(observation),
+                    None => targets.push((key, vec![observation])),
+                }
+            }
+        }
+        for ((marker_type, label), observations) in targets {
+            if observations.len() < 2 {
+                continue;
+            }
+            let name = format!("{}/{}", group.id, label);
+            match intersect(&observations) {
+                Ok(intersection) => objects.push(PObject {
+                    group: group.id.clone(),
+                    marker_type: marker_type.to_string(),
+                    label: label.to_string(),
+                    lonlat: vec![intersection.lon, intersection.lat],
+                    height: intersection.height,
+                    residual: intersection.residual,
+                    images: observations.into_iter().map(|o| o.image).collect(),
+                }),
+                Err(reason) => failures.push((name, reason)),
+            }
+        }
+    }
+    (objects, failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geodesy;
+
+    fn observe(image: &str, lon: f64, lat: f64, target: (f64, f64)) -> Observation {
+        Observation {
+            image: image.to_string(),
+            lon,
+            lat,
+            altitude: Some(100.0),
+            bearing: geodesy::bearing(lon, lat, target.0, target.1),
+            pitch: (10.0 / geodesy::distance(lon, lat, target.0, target.1)).atan(),
+        }
+    }
+
+    #[test]
+    fn recovers_target_from_two_rays() {
+        let target = (116.3975, 39.9087);
+        let observations = [
+            observe("a", 116.3970, 39.9085, target),
+            observe("b", 116.3981, 39.9084, target),
+        ];
+        let result = intersect(&observations).unwrap();
+        assert!(geodesy::distance(result.lon, result.lat, target.0, target.1) < 0.05);
+        assert!(result.residual < 0.01);
+        //目标比拍摄点高10米
+        assert!((result.height.unwrap() - 110.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn residual_reflects_inconsistent_rays() {
+        let target = (116.3975, 39.9087);
+        let mut observations = vec![
+            observe("a", 116.3970, 39.9085, target),
+            observe("b", 116.3981, 39.9084, target),
+            observe("c", 116.3976, 39.9092, target),
+        ];
+        observations[2].bearing += 3.0;
+        observations[2].altitude = None;
+        let result = intersect(&observations).unwrap();
+        assert!(result.residual > 0.5 && result.residual < 10.0);
+    }
+
+    #[test]
+    fn keeps_sea_level_altitude() {
+        let target = (116.3975, 39.9087);
+        let mut observations = [
+            observe("a", 116.3970, 39.9085, target),
+            observe("b", 116.3981, 39.9084, target),
+        ];
+        for o in observations.iter_mut() {
+            o.altitude = Some(0.0);
+        }
+        let result = intersect(&observations).unwrap();
+        //海拔为0的拍摄点仍参与推算高度
+        assert!((result.height.unwrap() - 10.0).abs() < 0.05);
+        for o in observations.iter_mut() {
+            o.altitude = None;
+        }
+        assert_eq!(intersect(&observations).unwrap().height, None);
+    }
+
+    #[test]
+    fn rejects_parallel_and_backward_rays() {
+        let target = (116.3975, 39.9087);
+        let parallel = [
+            observe("a", 116.3975, 39.9080, target),
+            observe("b", 116.3975, 39.9070, target),
+        ];
+        assert!(intersect(&parallel).is_err());
+        let mut backward = [
+            observe("a", 116.3970, 39.9085, target),
+            observe("b", 116.3981, 39.9084, target),
+        ];
+        backward[0].bearing += 180.0;
+        backward[1].bearing += 180.0;
+        assert!(intersect(&backward).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// 从JPEG的APP1段中提取XMP数据包
+pub fn read_xmp_from_jpeg(data: &[u8]) -> Option<String> {
+    if data.len() < 4 || data[0] != 0xFF || data[1] != 0xD8 {
+        return None;
+    }
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        //SOS之后为图像数据，不再有元数据段
+        if marker == 0xDA || marker == 0xD9 {
+            return None;
+        }
+        if marker == 0xFF || (0xD0..=0xD7).contains(&marker) || marker == 0x01 {
+            pos += if marker == 0xFF { 1 } else { 2 };
+            continue;
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let end = (pos + 2 + len).min(data.len());
+        let segment = &data[(pos + 4).min(end)..end];
+        if marker == 0xE1 && segment.starts_with(XMP_HEADER) {
+            return Some(String::from_utf8_lossy(&segment[XMP_HEADER.len()..]).into_owned());
+        }
+        pos = end;
+    }
+    None
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+const TIFF_XMP_TAG: u16 = 700;
+
+/// 从PNG的iTXt块中提取XMP数据包，不支持压缩的iTXt
+pub fn read_xmp_from_png(data: &[u8]) -> Option<String> {
+    if !data.starts_with(PNG_SIGNATURE) {
+        return None;
+    }
+    let mut pos = PNG_SIGNATURE.len();
+    while pos + 8 <= data.len() {
+        let len = u32::from_be_bytes(data[pos..pos + 4].try_into().ok()?) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        let chunk = data.get(pos + 8..pos + 8 + len)?;
+        if kind == b"IEND" || kind == b"IDAT" {
+            return None;
+        }
+        //关键字\0 压缩标记 压缩方法 语言\0 翻译关键字\0 文本
+        if kind == b"iTXt" && chunk.starts_with(PNG_XMP_KEYWORD) {
+            let rest = chunk.get(PNG_XMP_KEYWORD.len() + 1..)?;
+            if rest.first() != Some(&0) {
+                return None;
+            }
+            let mut text = rest.get(2..)?;
+            for _ in 0..2 {
+                let end = text.iter().position(|&b| b == 0)?;
+                text = &text[end + 1..];
+            }
+            return Some(String::from_utf8_lossy(text).into_owned());
+        }
+        //长度、类型、数据及CRC
+        pos += 12 + len;
+    }
+    None
+}
+
+/// 从TIFF第一个IFD的XMLPacket标签中提取XMP数据包
+pub fn read_xmp_from_tiff(data: &[u8]) -> Option<String> {
+    let little_endian = match data.get(0..4)? {
+        b"II*\0" => true,
+        b"MM\0*" => false,
+        _ => return None,
+    };
+    let read_u16 = |pos: usize| -> Option<u16> {
+        let bytes = data.get(pos..pos + 2)?.try_into().ok()?;
+        Some(if little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    };
+    let read_u32 = |pos: usize| -> Option<u32> {
+        let bytes = data.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+    let ifd = read_u32(4)? as usize;
+    for i in 0..read_u16(ifd)? as usize {
+        let entry = ifd + 2 + i * 12;
+        if read_u16(entry)? != TIFF_XMP_TAG {
+            continue;
+        }
+        //BYTE或UNDEFINED类型，数量即字节数，不超过4字节时直接存放在条目中
+        let len = read_u32(entry + 4)? as usize;
+        let offset = if len <= 4 {
+            entry + 8
+        } else {
+            read_u32(entry + 8)? as usize
+        };
+        return Some(String::from_utf8_lossy(data.get(offset..offset + len)?).into_owned());
+    }
+    None
+}
+
+/// 按文件格式提取XMP数据包，支持JPEG、PNG及TIFF
+pub fn read_xmp(data: &[u8]) -> Option<String> {
+    read_xmp_from_jpeg(data)
+        .or_else(|| read_xmp_from_png(data))
+        .or_else(|| read_xmp_from_tiff(data))
+}
+
+/// 读取XMP中的属性值，兼容属性写法name="v"和元素写法<name>v</name>
+pub fn xmp_value<'a>(xmp: &'a str, name: &str) -> Option<&'a str> {
+    let attr = format!("{}=", name);
+    if let Some(start) = xmp.find(&attr) {
+        let rest = &xmp[start + attr.len()..];
+        let quote = rest.chars().next()?;
+        if quote == '"' || quote == '\'' {
+            let rest = &rest[1..];
+            return rest.find(quote).map(|end| rest[..end].trim());
+        }
+    }
+    let open = format!("<{}>", name);
+    if let Some(start) = xmp.find(&open) {
+        let rest = &xmp[start + open.len()..];
+        let close = format!("</{}>", name);
+        return rest.find(&close).map(|end| rest[..end].trim());
+    }
+    None
+}
+
+pub fn xmp_f64(xmp: &str, name: &str) -> Option<f64> {
+    xmp_value(xmp, name)?.parse().ok()
+}
+
+/// Google Photo Sphere XMP中描述全景图裁切与姿态的字段
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GPano {
+    pub cropped_width: u32,
+    pub cropped_height: u32,
+    pub full_width: u32,
+    pub full_height: u32,
+    pub cropped_left: u32,
+    pub cropped_top: u32,
+    pub pose_pitch: Option<f64>,
+    pub pose_roll: Option<f64>,
+}
+
+/// 读取GPano裁切信息，缺少完整画布宽度或裁切区域宽度时返回None，其余缺失字段按完整全景推算
+pub fn read_gpano(xmp: &str) -> Option<GPano> {
+    let int = |name: &str| xmp_f64(xmp, name).map(|v| v.round().max(0.0) as u32);
+    let full_width = int("GPano:FullPanoWidthPixels")?;
+    let cropped_width = int("GPano:CroppedAreaImageWidthPixels")?;
+    let full_height = int("GPano:FullPanoHeightPixels").unwrap_or(full_width / 2);
+    let cropped_height = int("GPano:CroppedAreaImageHeightPixels").unwrap_or(full_height);
+    Some(GPano {
+        cropped_width,
+        cropped_height,
+        full_width,
+        full_height,
+        cropped_left: int("GPano:CroppedAreaLeftPixels").unwrap_or(0),
+        cropped_top: int("GPano:CroppedAreaTopPixels").unwrap_or(0),
+        pose_pitch: xmp_f64(xmp, "GPano:PosePitchDegrees"),
+        pose_roll: xmp_f64(xmp, "GPano:PoseRollDegrees"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKET: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF><rdf:Description
+        GPano:FullPanoWidthPixels="8000" GPano:CroppedAreaImageWidthPixels='8000'
+        GPano:CroppedAreaImageHeightPixels="3000" GPano:CroppedAreaTopPixels="500">
+        <GPano:PosePitchDegrees> 2.5 </GPano:PosePitchDegrees>
+        </rdf:Description></rdf:RDF></x:xmpmeta>"#;
+
+    //JPEG段：标记及包含长度字段在内的段长度
+    fn segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xFF, marker];
+        data.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    //PNG块，读取时不校验CRC
+    fn chunk(kind: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut data = (payload.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(payload);
+        data.extend_from_slice(&[0; 4]);
+        data
+    }
+
+    #[test]
+    fn reads_gpano_fields() {
+        let gpano = read_gpano(PACKET).unwrap();
+        assert_eq!((gpano.full_width, gpano.full_height), (8000, 4000));
+        assert_eq!((gpano.cropped_width, gpano.cropped_height), (8000, 3000));
+        assert_eq!((gpano.cropped_left, gpano.cropped_top), (0, 500));
+        assert_eq!((gpano.pose_pitch, gpano.pose_roll), (Some(2.5), None));
+        assert!(read_gpano(r#"GPano:FullPanoWidthPixels="8000""#).is_none());
+        assert!(read_gpano(r#"GPano:CroppedAreaImageWidthPixels="8000""#).is_none());
+    }
+
+    #[test]
+    fn reads_jpeg_app1() {
+        let mut data = vec![0xFF, 0xD8];
+        data.extend(segment(0xE0, b"JFIF\0\x01\x02\0\0\x01\0\x01\0\0"));
+        data.extend(segment(0xE1, b"Exif\0\0MM\0*"));
+        let mut xmp = XMP_HEADER.to_vec();
+        xmp.extend_from_slice(PACKET.as_bytes());
+        data.extend(segment(0xE1, &xmp));
+        data.extend_from_slice(&[0xFF, 0xDA, 0, 2, 0xFF, 0xD9]);
+        assert_eq!(read_xmp_from_jpeg(&data).as_deref(), Some(PACKET));
+        assert_eq!(read_xmp(&data).as_deref(), Some(PACKET));
+
+        //SOS之后的数据不再查找
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xDA, 0, 2];
+        data.extend(segment(0xE1, &xmp));
+        assert!(read_xmp_from_jpeg(&data).is_none());
+        assert!(read_xmp_from_jpeg(b"GIF89a").is_none());
+    }
+
+    #[test]
+    fn reads_png_itxt() {
+        let mut itxt = PNG_XMP_KEYWORD.to_vec();
+        itxt.extend_from_slice(b"\0\0\0en\0\0");
+        itxt.extend_from_slice(PACKET.as_bytes());
+        let mut data = PNG_SIGNATURE.to_vec();
+        data.extend(chunk(b"IHDR", &[0; 13]));
+        data.extend(chunk(b"tEXt", b"Comment\0panorama"));
+        data.extend(chunk(b"iTXt", &itxt));
+        data.extend(chunk(b"IEND", &[]));
+        assert_eq!(read_xmp_from_png(&data).as_deref(), Some(PACKET));
+        assert_eq!(read_xmp(&data).as_deref(), Some(PACKET));
+
+        //压缩的iTXt不支持
+        itxt[PNG_XMP_KEYWORD.len() + 1] = 1;
+        let mut data = PNG_SIGNATURE.to_vec();
+        data.extend(chunk(b"iTXt", &itxt));
+        assert!(read_xmp_from_png(&data).is_none());
+    }
+
+    #[test]
+    fn reads_tiff_tag_700() {
+        //小端，两个条目，XMP存放在IFD之后
+        let mut data = b"II*\0".to_vec();
+        data.extend_from_slice(&8u32.to_le_bytes());
+        data.extend_from_slice(&2u16.to_le_bytes());
+        for (tag, kind, count, value) in [
+            (256u16, 3u16, 1u32, 800u32),
+            (TIFF_XMP_TAG, 1, PACKET.len() as u32, 38),
+        ] {
+            data.extend_from_slice(&tag.to_le_bytes());
+            data.extend_from_slice(&kind.to_le_bytes());
+            data.extend_from_slice(&count.to_le_bytes());
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(PACKET.as_bytes());
+        assert_eq!(read_xmp_from_tiff(&data).as_deref(), Some(PACKET));
+        assert_eq!(read_xmp(&data).as_deref(), Some(PACKET));
+
+        //大端，不超过4字节时直接存放在条目中
+        let mut data = b"MM\0*".to_vec();
+        data.extend_from_slice(&8u32.to_be_bytes());
+        data.extend_from_slice(&1u16.to_be_bytes());
+        data.extend_from_slice(&TIFF_XMP_TAG.to_be_bytes());
+        data.extend_from_slice(&7u16.to_be_bytes());
+        data.extend_from_slice(&3u32.to_be_bytes());
+        data.extend_from_slice(b"xmp\0");
+        assert_eq!(read_xmp_from_tiff(&data).as_deref(), Some("xmp"));
+        assert!(read_xmp_from_tiff(b"II*\0").is_none());
+    }
+}